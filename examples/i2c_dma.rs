@@ -38,7 +38,7 @@ fn main() -> ! {
     let mut green = gpiob.pb5.into_push_pull_output();
     let mut red = gpiob.pb7.into_push_pull_output();
 
-    let mut i2c = dp.I2C1.i2c(sda, scl, 100.khz(), &mut rcc);
+    let mut i2c = dp.I2C1.i2c(sda, scl, 100.khz(), &mut rcc).unwrap();
 
     let mut tx_channel = dma.channels.channel2;
     let mut rx_channel = dma.channels.channel3;
@@ -0,0 +1,68 @@
+#![deny(warnings)]
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use cortex_m_rt::entry;
+use stm32l0xx_hal::{
+    exti::{DirectLine, Exti},
+    pac,
+    prelude::*,
+    pwr::{self, PWR},
+    rcc::Config,
+    serial::{self, WakeupClock, WakeupSource},
+};
+
+use nb::block;
+
+#[entry]
+fn main() -> ! {
+    let cp = pac::CorePeripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    let mut rcc = dp.RCC.freeze(Config::hsi16());
+    let mut exti = Exti::new(dp.EXTI);
+    let mut pwr = PWR::new(dp.PWR, &mut rcc);
+    let mut scb = cp.SCB;
+
+    let gpioa = dp.GPIOA.split(&mut rcc);
+
+    let mut serial = dp
+        .LPUART1
+        .usart(
+            gpioa.pa2,
+            gpioa.pa3,
+            serial::Config::default().baudrate(9_600.Bd()),
+            &mut rcc,
+        )
+        .unwrap();
+
+    // Keep LPUART1 clocked by HSI16 in Stop mode and wake up on a start bit.
+    serial
+        .enable_wakeup(WakeupSource::StartBit, WakeupClock::HSI16, &mut rcc)
+        .unwrap();
+
+    exti.listen_direct(DirectLine::Lpuart1);
+
+    loop {
+        exti.wait_for_irq(
+            DirectLine::Lpuart1,
+            pwr.stop_mode(
+                &mut scb,
+                &mut rcc,
+                pwr::StopModeConfig {
+                    ultra_low_power: true,
+                },
+            ),
+        );
+
+        serial.clear_wakeup_flag();
+
+        // Echo the character that woke us up.
+        let received = block!(serial.read()).unwrap();
+        block!(serial.write(received)).ok();
+        block!(serial.flush()).ok();
+    }
+}
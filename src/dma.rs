@@ -126,14 +126,19 @@ where
         Word: SupportedWordSize,
     {
         assert!(buffer.len() >= num_words);
-        assert!(num_words <= u16::max_value() as usize);
         assert_eq!(buffer.as_ptr().align_offset(mem::size_of::<Word>()), 0);
 
-        channel.select_target(handle, &target);
-        channel.set_peripheral_address(handle, address);
-        channel.set_memory_address(handle, buffer.as_ptr() as u32);
-        channel.set_transfer_len(handle, num_words as u16);
-        channel.configure::<Word>(handle, priority.0, dir.0, circular);
+        configure_channel::<T, C, Word>(
+            handle,
+            &target,
+            &channel,
+            buffer.as_ptr() as u32,
+            num_words,
+            address,
+            priority,
+            dir,
+            circular,
+        );
 
         Transfer {
             res: TransferResources {
@@ -204,6 +209,27 @@ where
         Ok(self.res)
     }
 
+    /// Stops the transfer before it is finished
+    ///
+    /// Returns the owned resources, along with the number of words that have
+    /// not been transferred. This is useful to end a peripheral-to-memory
+    /// transfer of unknown length, for example on a serial receiver timeout.
+    pub fn stop(self) -> (TransferResources<T, C, B>, u16) {
+        self.res.channel.stop();
+
+        compiler_fence(Ordering::SeqCst);
+
+        let (remaining, _, _) = self.res.channel.transfer_state();
+        self.res.channel.clear_flags();
+
+        (self.res, remaining)
+    }
+
+    /// Indicates whether a transfer error has occurred, and clears it
+    pub(crate) fn error_occured(&self) -> bool {
+        self.res.channel.error_occured()
+    }
+
     /// Returns some transfer state
     ///
     /// The number of items to transfer, the half transfer flag, and the
@@ -221,6 +247,44 @@ where
     }
 }
 
+/// Configures a channel without taking ownership of the memory
+///
+/// This is used for transfers that share their memory with another transfer.
+///
+/// # Safety
+///
+/// The caller must make sure that the memory at `memory_address` is valid for
+/// `num_words` words, and can be accessed safely in the given direction, for
+/// as long as the transfer is running.
+///
+/// # Panics
+///
+/// Panics, if `num_words` is larger than `u16::max_value()`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn configure_channel<T, C, Word>(
+    handle: &mut Handle,
+    target: &T,
+    channel: &C,
+    memory_address: u32,
+    num_words: usize,
+    address: u32,
+    priority: Priority,
+    dir: Direction,
+    circular: bool,
+) where
+    T: Target<C>,
+    C: Channel,
+    Word: SupportedWordSize,
+{
+    assert!(num_words <= u16::max_value() as usize);
+
+    channel.select_target(handle, target);
+    channel.set_peripheral_address(handle, address);
+    channel.set_memory_address(handle, memory_address);
+    channel.set_transfer_len(handle, num_words as u16);
+    channel.configure::<Word>(handle, priority.0, dir.0, circular);
+}
+
 pub struct TransferResources<T, C, B> {
     pub target: T,
     pub channel: C,
@@ -274,7 +338,11 @@ impl Direction {
 #[derive(Debug)]
 pub struct Error;
 
-pub trait Channel: Sized {
+mod sealed {
+    pub trait Sealed {}
+}
+
+pub trait Channel: Sized + sealed::Sealed {
     fn select_target<T: Target<Self>>(&self, _: &mut Handle, target: &T);
     fn set_peripheral_address(&self, _: &mut Handle, address: u32);
     fn set_memory_address(&self, _: &mut Handle, address: u32);
@@ -289,6 +357,9 @@ pub trait Channel: Sized {
     fn error_occured(&self) -> bool;
     fn transfer_state(&self) -> (u16, bool, bool);
     fn clear_flags(&self);
+
+    /// Disables the channel, ending the current transfer
+    fn stop(&self);
 }
 
 macro_rules! impl_channel {
@@ -327,6 +398,8 @@ macro_rules! impl_channel {
         $(
             pub struct $channel(());
 
+            impl sealed::Sealed for $channel {}
+
             impl Channel for $channel {
                 fn select_target<T: Target<Self>>(&self,
                     handle:  &mut Handle,
@@ -472,6 +545,15 @@ macro_rules! impl_channel {
                             .$ctcif().clear()
                     );
                 }
+
+                fn stop(&self) {
+                    // Safe, because we're only accessing a register that this
+                    // channel has exclusive access to.
+                    let ccr = &unsafe { &*pac::DMA1::ptr() }.$chfield.cr;
+
+                    // Stop transfer
+                    ccr.modify(|_, w| w.en().disabled());
+                }
             }
         )*
     }
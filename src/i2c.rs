@@ -14,8 +14,9 @@ use crate::pac::i2c1::{
     cr2::{AUTOEND_A, RD_WRN_A},
     RegisterBlock,
 };
-use crate::rcc::{Enable, Rcc, Reset};
-use cast::u8;
+use crate::rcc::{Enable, Rcc, Reset, HSI_FREQ};
+use crate::syscfg::SYSCFG;
+use embedded_time::duration::Nanoseconds;
 use embedded_time::rate::Hertz;
 
 // I²C traits
@@ -54,6 +55,9 @@ use crate::{
     pac::I2C1,
 };
 
+pub mod smbus;
+pub mod target;
+
 /// I2C abstraction
 pub struct I2c<I2C, SDA, SCL> {
     i2c: I2C,
@@ -65,12 +69,70 @@ impl<I, SDA, SCL> I2c<I, SDA, SCL>
 where
     I: Instance,
 {
-    pub fn new(i2c: I, sda: SDA, scl: SCL, freq: Hertz, rcc: &mut Rcc) -> Self
+    /// Configures the peripheral as a controller
+    ///
+    /// Returns `InvalidConfig`, if no timing meeting the requirements of the
+    /// configured mode can be derived from the kernel clock. Bus frequencies
+    /// above 400 kHz require `new_fast_mode_plus`, and are rejected too.
+    pub fn new<C>(
+        i2c: I,
+        sda: SDA,
+        scl: SCL,
+        config: C,
+        rcc: &mut Rcc,
+    ) -> Result<Self, InvalidConfig>
+    where
+        I: Instance,
+        SDA: SDAPin<I>,
+        SCL: SCLPin<I>,
+        C: Into<Config>,
+    {
+        let config = config.into();
+        if config.is_fast_mode_plus() {
+            return Err(InvalidConfig);
+        }
+
+        Self::init(i2c, sda, scl, &config, rcc)
+    }
+
+    /// Configures the peripheral as a controller, with the Fast-mode Plus
+    /// drive capability of the SDA and SCL pins enabled
+    ///
+    /// Required for bus frequencies above 400 kHz. The drive capability stays
+    /// enabled after `release`.
+    pub fn new_fast_mode_plus<C>(
+        i2c: I,
+        sda: SDA,
+        scl: SCL,
+        config: C,
+        syscfg: &mut SYSCFG,
+        rcc: &mut Rcc,
+    ) -> Result<Self, InvalidConfig>
     where
         I: Instance,
         SDA: SDAPin<I>,
         SCL: SCLPin<I>,
+        C: Into<Config>,
     {
+        let i2c = Self::init(i2c, sda, scl, &config.into(), rcc)?;
+        I::enable_fast_mode_plus(syscfg);
+
+        Ok(i2c)
+    }
+
+    fn init(
+        i2c: I,
+        sda: SDA,
+        scl: SCL,
+        config: &Config,
+        rcc: &mut Rcc,
+    ) -> Result<Self, InvalidConfig>
+    where
+        SDA: SDAPin<I>,
+        SCL: SCLPin<I>,
+    {
+        let timing = Timing::calculate(I::kernel_clk(rcc), config)?;
+
         sda.setup();
         scl.setup();
 
@@ -79,76 +141,9 @@ where
         // Reset I2C
         I::reset(rcc);
 
-        let freq = freq.0;
-
-        assert!(freq <= 1_000_000);
-
-        // TODO review compliance with the timing requirements of I2C
-        // t_I2CCLK = 1 / PCLK1
-        // t_PRESC  = (PRESC + 1) * t_I2CCLK
-        // t_SCLL   = (SCLL + 1) * t_PRESC
-        // t_SCLH   = (SCLH + 1) * t_PRESC
-        //
-        // t_SYNC1 + t_SYNC2 > 4 * t_I2CCLK
-        // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
-        let i2cclk = rcc.clocks.apb1_clk().0;
-        let ratio = i2cclk / freq - 4;
-        let (presc, scll, sclh, sdadel, scldel) = if freq >= 100_000 {
-            // fast-mode or fast-mode plus
-            // here we pick SCLL + 1 = 2 * (SCLH + 1)
-            let presc = ratio / 387;
-
-            let sclh = ((ratio / (presc + 1)) - 3) / 3;
-            let scll = 2 * (sclh + 1) - 1;
-
-            let (sdadel, scldel) = if freq > 400_000 {
-                // fast-mode plus
-                let sdadel = 0;
-                let scldel = i2cclk / 4_000_000 / (presc + 1) - 1;
-
-                (sdadel, scldel)
-            } else {
-                // fast-mode
-                let sdadel = i2cclk / 8_000_000 / (presc + 1);
-                let scldel = i2cclk / 2_000_000 / (presc + 1) - 1;
-
-                (sdadel, scldel)
-            };
-
-            (presc, scll, sclh, sdadel, scldel)
-        } else {
-            // standard-mode
-            // here we pick SCLL = SCLH
-            let presc = ratio / 514;
-
-            let sclh = ((ratio / (presc + 1)) - 2) / 2;
-            let scll = sclh;
-
-            let sdadel = i2cclk / 2_000_000 / (presc + 1);
-            let scldel = i2cclk / 800_000 / (presc + 1) - 1;
-
-            (presc, scll, sclh, sdadel, scldel)
-        };
-
-        let presc = u8(presc).unwrap();
-        assert!(presc < 16);
-        let scldel = u8(scldel).unwrap();
-        assert!(scldel < 16);
-        let sdadel = u8(sdadel).unwrap();
-        assert!(sdadel < 16);
-        let sclh = u8(sclh).unwrap();
-        let scll = u8(scll).unwrap();
-
-        // Configure for "fast mode" (400 KHz)
-        i2c.timingr.write(|w| {
-            w.presc().bits(presc);
-            w.scll().bits(scll);
-            w.sclh().bits(sclh);
-            w.sdadel().bits(sdadel);
-            w.scldel().bits(scldel)
-        });
+        timing.write(&i2c, config);
 
-        i2c.cr1.write(|w| {
+        i2c.cr1.modify(|_, w| {
             // Enable DMA reception
             w.rxdmaen().set_bit();
             // Enable DMA transmission
@@ -157,7 +152,7 @@ where
             w.pe().set_bit()
         });
 
-        I2c { i2c, sda, scl }
+        Ok(I2c { i2c, sda, scl })
     }
 
     pub fn release(self) -> (I, SDA, SCL) {
@@ -175,6 +170,12 @@ where
         } else if isr.nackf().bit_is_set() {
             self.i2c.icr.write(|w| w.nackcf().set_bit());
             Err(Error::Nack)
+        } else if isr.pecerr().bit_is_set() {
+            self.i2c.icr.write(|w| w.peccf().set_bit());
+            Err(Error::PECError)
+        } else if isr.timeout().bit_is_set() {
+            self.i2c.icr.write(|w| w.timoutcf().set_bit());
+            Err(Error::Timeout)
         } else {
             Ok(())
         }
@@ -373,6 +374,258 @@ where
     }
 }
 
+/// I2C bus configuration
+///
+/// The timing is calculated to meet the requirements of the I2C specification
+/// (UM10204) for the mode selected by the bus frequency: Standard-mode (up to
+/// 100 kHz), Fast-mode (up to 400 kHz) or Fast-mode Plus (up to 1 MHz). Fast-mode
+/// Plus also requires the drive capability of the pins to be enabled, which is
+/// done by the `new_fast_mode_plus` constructors.
+#[derive(Clone, Copy)]
+pub struct Config {
+    freq: Hertz,
+    rise_time: Nanoseconds,
+    fall_time: Nanoseconds,
+    analog_filter: bool,
+    digital_filter: u8,
+}
+
+impl Config {
+    pub fn new(freq: Hertz) -> Self {
+        Config {
+            freq,
+            rise_time: Nanoseconds(100),
+            fall_time: Nanoseconds(10),
+            analog_filter: true,
+            digital_filter: 0,
+        }
+    }
+
+    /// Sets the SCL/SDA rise time (100 ns by default)
+    ///
+    /// The rise time depends on the bus capacitance and the pull-up
+    /// resistors, and should be measured.
+    pub fn rise_time(mut self, rise_time: Nanoseconds) -> Self {
+        self.rise_time = rise_time;
+        self
+    }
+
+    /// Sets the SCL/SDA fall time (10 ns by default)
+    pub fn fall_time(mut self, fall_time: Nanoseconds) -> Self {
+        self.fall_time = fall_time;
+        self
+    }
+
+    /// Enables the analog noise filter (enabled by default)
+    ///
+    /// The delay of the filter reduces the margin for the data hold time. In
+    /// Fast-mode Plus, it's only usable with fast edges.
+    pub fn analog_filter(mut self, enable: bool) -> Self {
+        self.analog_filter = enable;
+        self
+    }
+
+    /// Sets the digital noise filter length, in kernel clock cycles (0-15)
+    ///
+    /// Disabled (0) by default.
+    pub fn digital_filter(mut self, cycles: u8) -> Self {
+        self.digital_filter = cycles;
+        self
+    }
+}
+
+impl Config {
+    /// Returns true, if the bus frequency requires Fast-mode Plus
+    fn is_fast_mode_plus(&self) -> bool {
+        self.freq.0 > 400_000
+    }
+}
+
+impl From<Hertz> for Config {
+    fn from(freq: Hertz) -> Self {
+        Config::new(freq)
+    }
+}
+
+/// Timing requirements of an I2C mode, in picoseconds
+struct Spec {
+    low_min: u64,
+    high_min: u64,
+    setup_min: u64,
+    hold_min: u64,
+    valid_max: u64,
+    rise_max: u64,
+    fall_max: u64,
+}
+
+impl Spec {
+    fn for_freq(freq: u32) -> Self {
+        if freq <= 100_000 {
+            // Standard-mode
+            Spec {
+                low_min: 4_700_000,
+                high_min: 4_000_000,
+                setup_min: 250_000,
+                hold_min: 0,
+                valid_max: 3_450_000,
+                rise_max: 1_000_000,
+                fall_max: 300_000,
+            }
+        } else if freq <= 400_000 {
+            // Fast-mode
+            Spec {
+                low_min: 1_300_000,
+                high_min: 600_000,
+                setup_min: 100_000,
+                hold_min: 0,
+                valid_max: 900_000,
+                rise_max: 300_000,
+                fall_max: 300_000,
+            }
+        } else {
+            // Fast-mode Plus
+            Spec {
+                low_min: 500_000,
+                high_min: 260_000,
+                setup_min: 50_000,
+                hold_min: 0,
+                valid_max: 450_000,
+                rise_max: 120_000,
+                fall_max: 120_000,
+            }
+        }
+    }
+}
+
+/// TIMINGR register values
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Timing {
+    presc: u8,
+    scll: u8,
+    sclh: u8,
+    sdadel: u8,
+    scldel: u8,
+}
+
+impl Timing {
+    /// Calculates the timing for the given I2C kernel clock
+    ///
+    /// Picks the SCL period closest to, but not shorter than, the period of
+    /// the requested frequency. Fails, if the resulting frequency would be
+    /// below 80 % of the requested one, or if the data setup and hold times
+    /// can't be met.
+    fn calculate(i2cclk: Hertz, config: &Config) -> Result<Self, InvalidConfig> {
+        const PS_PER_S: u64 = 1_000_000_000_000;
+
+        let freq = config.freq.0;
+        if freq == 0 || freq > 1_000_000 || i2cclk.0 == 0 || config.digital_filter > 15 {
+            return Err(InvalidConfig);
+        }
+
+        let spec = Spec::for_freq(freq);
+        let rise = u64::from(config.rise_time.0) * 1000;
+        let fall = u64::from(config.fall_time.0) * 1000;
+        if rise > spec.rise_max || fall > spec.fall_max {
+            return Err(InvalidConfig);
+        }
+
+        let t_clk = PS_PER_S / u64::from(i2cclk.0);
+        let dnf = u64::from(config.digital_filter);
+        let (af_min, af_max) = if config.analog_filter {
+            (50_000, 260_000)
+        } else {
+            (0, 0)
+        };
+        let dnf_delay = dnf * t_clk;
+
+        // Data hold time: SDA must change after SCL has fallen, but early
+        // enough to be valid before the data valid time has passed
+        let sdadel_min = (fall + spec.hold_min).saturating_sub(af_min + (dnf + 3) * t_clk);
+        let sdadel_max = spec
+            .valid_max
+            .checked_sub(rise + af_max + (dnf + 4) * t_clk)
+            .ok_or(InvalidConfig)?;
+        // Data setup time
+        let scldel_min = rise + spec.setup_min;
+
+        // Delay between SCL changing on the bus and being detected internally
+        let sync = af_min + dnf_delay + 2 * t_clk;
+
+        let period = PS_PER_S / u64::from(freq);
+        let period_max = period * 5 / 4;
+
+        let mut best: Option<(u64, Timing)> = None;
+        for presc in 0..16u8 {
+            let t_presc = u64::from(presc + 1) * t_clk;
+
+            // tSCLDEL = (SCLDEL + 1) * tPRESC
+            let scldel = scldel_min.div_ceil(t_presc).saturating_sub(1);
+            // tSDADEL = SDADEL * tPRESC
+            let sdadel = sdadel_min.div_ceil(t_presc);
+            if scldel > 15 || sdadel > 15 || sdadel * t_presc > sdadel_max {
+                continue;
+            }
+
+            for scll in 0..=255u8 {
+                let low = (u64::from(scll) + 1) * t_presc + sync;
+                if low < spec.low_min || low <= 4 * t_clk + af_min + dnf_delay {
+                    continue;
+                }
+
+                // Shortest high period that doesn't exceed the requested
+                // frequency
+                let high_min = spec.high_min.max(period.saturating_sub(low + rise + fall));
+                let sclh = high_min
+                    .saturating_sub(sync)
+                    .div_ceil(t_presc)
+                    .saturating_sub(1);
+                if sclh > 255 {
+                    continue;
+                }
+                let high = (sclh + 1) * t_presc + sync;
+
+                let actual = low + high + rise + fall;
+                if actual > period_max {
+                    continue;
+                }
+                let error = actual - period;
+                if let Some((best_error, _)) = best {
+                    if error >= best_error {
+                        continue;
+                    }
+                }
+                let timing = Timing {
+                    presc,
+                    scll,
+                    sclh: sclh as u8,
+                    sdadel: sdadel as u8,
+                    scldel: scldel as u8,
+                };
+                best = Some((error, timing));
+            }
+        }
+
+        best.map(|(_, timing)| timing).ok_or(InvalidConfig)
+    }
+
+    /// Configures the timing and noise filters
+    ///
+    /// Must be called while the peripheral is disabled.
+    fn write(&self, i2c: &RegisterBlock, config: &Config) {
+        i2c.timingr.write(|w| {
+            w.presc().bits(self.presc);
+            w.scll().bits(self.scll);
+            w.sclh().bits(self.sclh);
+            w.sdadel().bits(self.sdadel);
+            w.scldel().bits(self.scldel)
+        });
+        i2c.cr1.modify(|_, w| {
+            w.anfoff().bit(!config.analog_filter);
+            w.dnf().bits(config.digital_filter)
+        });
+    }
+}
+
 impl<I, SDA, SCL> WriteRead for I2c<I, SDA, SCL>
 where
     I: Instance,
@@ -457,6 +710,12 @@ where
 
 pub trait Instance: Deref<Target = RegisterBlock> + Enable + Reset {
     fn ptr() -> *const RegisterBlock;
+
+    /// Returns the frequency of the kernel clock selected in `RCC_CCIPR`
+    fn kernel_clk(rcc: &Rcc) -> Hertz;
+
+    /// Enables the Fast-mode Plus drive capability of the SDA and SCL pins
+    fn enable_fast_mode_plus(syscfg: &mut SYSCFG);
 }
 
 // I2C SDA pin
@@ -471,23 +730,42 @@ pub trait SCLPin<I2C> {
 
 // I2C error
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Overrun,
     Nack,
+    /// SMBus packet error check mismatch
     PECError,
+    /// SMBus clock low timeout
+    Timeout,
+    /// SMBus block without data, or longer than 32 bytes
+    BlockSize,
     BusError,
     ArbitrationLost,
 }
 
+/// Indicates that a configuration is invalid
+#[derive(Debug)]
+pub struct InvalidConfig;
+
 pub trait I2cExt<I2C> {
-    fn i2c<SDA, SCL>(self, sda: SDA, scl: SCL, freq: Hertz, rcc: &mut Rcc) -> I2c<I2C, SDA, SCL>
+    fn i2c<SDA, SCL, C>(
+        self,
+        sda: SDA,
+        scl: SCL,
+        config: C,
+        rcc: &mut Rcc,
+    ) -> Result<I2c<I2C, SDA, SCL>, InvalidConfig>
     where
         SDA: SDAPin<I2C>,
-        SCL: SCLPin<I2C>;
+        SCL: SCLPin<I2C>,
+        C: Into<Config>;
 }
 
 macro_rules! i2c {
     ($I2CX:ident,
+        fmp: $i2cX_fmp:ident,
+        $(sel: $i2cXsel:ident,)?
         sda: [ $(($PSDA:ty, $afsda:expr),)+ ],
         scl: [ $(($PSCL:ty, $afscl:expr),)+ ],
     ) => {
@@ -508,18 +786,19 @@ macro_rules! i2c {
         )+
 
         impl I2cExt<$I2CX> for $I2CX {
-            fn i2c<SDA, SCL>(
+            fn i2c<SDA, SCL, C>(
                 self,
                 sda: SDA,
                 scl: SCL,
-                freq: Hertz,
+                config: C,
                 rcc: &mut Rcc,
-            ) -> I2c<$I2CX, SDA, SCL>
+            ) -> Result<I2c<$I2CX, SDA, SCL>, InvalidConfig>
             where
                 SDA: SDAPin<$I2CX>,
                 SCL: SCLPin<$I2CX>,
+                C: Into<Config>,
             {
-                I2c::new(self, sda, scl, freq, rcc)
+                I2c::new(self, sda, scl, config, rcc)
             }
         }
 
@@ -527,6 +806,21 @@ macro_rules! i2c {
             fn ptr() -> *const RegisterBlock {
                 $I2CX::ptr()
             }
+
+            fn kernel_clk(rcc: &Rcc) -> Hertz {
+                $(
+                    match rcc.rb.ccipr.read().$i2cXsel().bits() {
+                        0b01 => return rcc.clocks.sys_clk(),
+                        0b10 => return Hertz(HSI_FREQ),
+                        _ => {}
+                    }
+                )?
+                rcc.clocks.apb1_clk()
+            }
+
+            fn enable_fast_mode_plus(syscfg: &mut SYSCFG) {
+                syscfg.syscfg.cfgr2.modify(|_, w| w.$i2cX_fmp().set_bit());
+            }
         }
     };
 }
@@ -534,6 +828,8 @@ macro_rules! i2c {
 #[cfg(feature = "io-STM32L021")]
 i2c!(
     I2C1,
+    fmp: i2c1_fmp,
+    sel: i2c1sel,
     sda: [
         (PA10<Output<OpenDrain>>, AltMode::AF1),
         (PA13<Output<OpenDrain>>, AltMode::AF3),
@@ -550,6 +846,8 @@ i2c!(
 #[cfg(feature = "io-STM32L031")]
 i2c!(
     I2C1,
+    fmp: i2c1_fmp,
+    sel: i2c1sel,
     sda: [
         (PA10<Output<OpenDrain>>, AltMode::AF1),
         (PB7<Output<OpenDrain>>, AltMode::AF1),
@@ -565,6 +863,8 @@ i2c!(
 #[cfg(feature = "io-STM32L051")]
 i2c!(
     I2C1,
+    fmp: i2c1_fmp,
+    sel: i2c1sel,
     sda: [
         (PB7<Output<OpenDrain>>, AltMode::AF1),
         (PB9<Output<OpenDrain>>, AltMode::AF4),
@@ -578,6 +878,7 @@ i2c!(
 #[cfg(feature = "io-STM32L051")]
 i2c!(
     I2C2,
+    fmp: i2c2_fmp,
     sda: [
         (PB11<Output<OpenDrain>>, AltMode::AF6),
         (PB14<Output<OpenDrain>>, AltMode::AF5),
@@ -591,6 +892,8 @@ i2c!(
 #[cfg(feature = "io-STM32L071")]
 i2c!(
     I2C1,
+    fmp: i2c1_fmp,
+    sel: i2c1sel,
     sda: [
         (PA10<Output<OpenDrain>>, AltMode::AF6),
         (PB7<Output<OpenDrain>>, AltMode::AF1),
@@ -606,6 +909,7 @@ i2c!(
 #[cfg(feature = "io-STM32L071")]
 i2c!(
     I2C2,
+    fmp: i2c2_fmp,
     sda: [
         (PB11<Output<OpenDrain>>, AltMode::AF6),
         (PB14<Output<OpenDrain>>, AltMode::AF5),
@@ -619,6 +923,8 @@ i2c!(
 #[cfg(feature = "io-STM32L071")]
 i2c!(
     I2C3,
+    fmp: i2c3_fmp,
+    sel: i2c3sel,
     sda: [
         (PB4<Output<OpenDrain>>, AltMode::AF7),
         (PC1<Output<OpenDrain>>, AltMode::AF7),
@@ -687,6 +993,21 @@ where
         self.inner.is_active()
     }
 
+    /// Stops the transfer before it is finished
+    ///
+    /// Returns the owned resources, along with the number of words that have
+    /// not been transferred. This is useful in target mode, where the
+    /// controller decides how many bytes are transferred.
+    pub fn stop(self) -> (dma::TransferResources<Target, Channel, Buffer>, u16) {
+        let (res, remaining) = self.inner.stop();
+        let res = dma::TransferResources {
+            target: self.target,
+            channel: res.channel,
+            buffer: res.buffer,
+        };
+        (res, remaining)
+    }
+
     /// Waits for the transfer to finish and returns the owned resources
     ///
     /// This function will busily wait until the transfer is finished. If you
@@ -718,3 +1039,119 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test-only methods.
+    impl Timing {
+        fn t_presc(&self, i2cclk: Hertz) -> u64 {
+            u64::from(self.presc + 1) * 1_000_000_000_000 / u64::from(i2cclk.0)
+        }
+
+        /// Returns the SCL low and high periods and the SCL period, in ps
+        fn scl_periods(&self, i2cclk: Hertz, config: &Config) -> (u64, u64, u64) {
+            let t_clk = 1_000_000_000_000 / u64::from(i2cclk.0);
+            let af_min = if config.analog_filter { 50_000 } else { 0 };
+            let sync = af_min + u64::from(config.digital_filter) * t_clk + 2 * t_clk;
+            let low = (u64::from(self.scll) + 1) * self.t_presc(i2cclk) + sync;
+            let high = (u64::from(self.sclh) + 1) * self.t_presc(i2cclk) + sync;
+            let rise = u64::from(config.rise_time.0) * 1000;
+            let fall = u64::from(config.fall_time.0) * 1000;
+            (low, high, low + high + rise + fall)
+        }
+
+        /// Asserts that the timing meets the requirements of the I2C mode
+        fn assert_valid(&self, i2cclk: Hertz, config: &Config) {
+            let spec = Spec::for_freq(config.freq.0);
+            let (low, high, period) = self.scl_periods(i2cclk, config);
+            assert!(low >= spec.low_min);
+            assert!(high >= spec.high_min);
+
+            // Not faster than requested, and not slower than 80 % of that
+            let requested = 1_000_000_000_000 / u64::from(config.freq.0);
+            assert!(period >= requested);
+            assert!(period <= requested * 5 / 4);
+
+            let rise = u64::from(config.rise_time.0) * 1000;
+            let setup = (u64::from(self.scldel) + 1) * self.t_presc(i2cclk);
+            assert!(setup >= rise + spec.setup_min);
+            assert!(self.sdadel < 16 && self.scldel < 16);
+        }
+    }
+
+    #[test]
+    fn standard_mode() {
+        for &i2cclk in &[2_097_000, 16_000_000, 32_000_000] {
+            let config = Config::new(Hertz(100_000));
+            let t = Timing::calculate(Hertz(i2cclk), &config).unwrap();
+            t.assert_valid(Hertz(i2cclk), &config);
+        }
+
+        // Slow edges
+        let config = Config::new(Hertz(100_000))
+            .rise_time(Nanoseconds(1000))
+            .fall_time(Nanoseconds(300));
+        let t = Timing::calculate(Hertz(16_000_000), &config).unwrap();
+        t.assert_valid(Hertz(16_000_000), &config);
+    }
+
+    #[test]
+    fn fast_mode() {
+        for &i2cclk in &[8_000_000, 16_000_000, 32_000_000] {
+            let config = Config::new(Hertz(400_000));
+            let t = Timing::calculate(Hertz(i2cclk), &config).unwrap();
+            t.assert_valid(Hertz(i2cclk), &config);
+        }
+
+        // Slow edges need a faster kernel clock
+        let config = Config::new(Hertz(400_000)).rise_time(Nanoseconds(300));
+        assert!(Timing::calculate(Hertz(8_000_000), &config).is_err());
+        let t = Timing::calculate(Hertz(16_000_000), &config).unwrap();
+        t.assert_valid(Hertz(16_000_000), &config);
+
+        // With digital filter, and without analog filter
+        let config = Config::new(Hertz(400_000))
+            .analog_filter(false)
+            .digital_filter(2);
+        let t = Timing::calculate(Hertz(32_000_000), &config).unwrap();
+        t.assert_valid(Hertz(32_000_000), &config);
+    }
+
+    #[test]
+    fn fast_mode_plus() {
+        for &i2cclk in &[16_000_000, 32_000_000] {
+            let config = Config::new(Hertz(1_000_000))
+                .rise_time(Nanoseconds(120))
+                .analog_filter(false);
+            let t = Timing::calculate(Hertz(i2cclk), &config).unwrap();
+            t.assert_valid(Hertz(i2cclk), &config);
+        }
+
+        // The analog filter delay only leaves room for fast edges
+        let config = Config::new(Hertz(1_000_000)).rise_time(Nanoseconds(50));
+        let t = Timing::calculate(Hertz(32_000_000), &config).unwrap();
+        t.assert_valid(Hertz(32_000_000), &config);
+        let config = Config::new(Hertz(1_000_000)).rise_time(Nanoseconds(120));
+        assert!(Timing::calculate(Hertz(32_000_000), &config).is_err());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let clk = Hertz(16_000_000);
+        assert!(Timing::calculate(clk, &Config::new(Hertz(0))).is_err());
+        assert!(Timing::calculate(clk, &Config::new(Hertz(1_000_001))).is_err());
+
+        // Rise time exceeds Fast-mode limit
+        let config = Config::new(Hertz(400_000)).rise_time(Nanoseconds(301));
+        assert!(Timing::calculate(clk, &config).is_err());
+
+        let config = Config::new(Hertz(100_000)).digital_filter(16);
+        assert!(Timing::calculate(clk, &config).is_err());
+
+        // Kernel clock too slow for Fast-mode Plus
+        let config = Config::new(Hertz(1_000_000));
+        assert!(Timing::calculate(Hertz(2_097_000), &config).is_err());
+    }
+}
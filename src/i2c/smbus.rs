@@ -0,0 +1,449 @@
+//! SMBus/PMBus
+//!
+//! `Smbus` wraps an `I2c` and acts as SMBus host, providing the standard
+//! byte, word and block commands, with optional packet error checking (PEC).
+//! SMBus device mode is provided by `I2cTarget::enable_smbus`.
+
+use embedded_time::duration::Microseconds;
+use embedded_time::rate::Hertz;
+
+use super::{Error, I2c, Instance, InvalidConfig};
+use crate::pac::i2c1::{
+    cr2::{AUTOEND_A, RD_WRN_A},
+    RegisterBlock,
+};
+use crate::rcc::Rcc;
+
+// I/O Imports
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+use crate::{
+    gpio::{gpiob::PB5, AltMode, OpenDrain, Output},
+    pac::I2C1,
+};
+
+/// Host address, used by devices for the Host Notify protocol
+const HOST_ADDRESS: u8 = 0b000_1000;
+
+/// Maximum number of data bytes in a block
+const BLOCK_MAX: usize = 32;
+
+/// SMBus configuration
+#[derive(Clone, Copy)]
+pub struct Config {
+    pec: bool,
+    timeout: Option<Microseconds>,
+    extended_timeout: Option<Microseconds>,
+    host_notify: bool,
+    default_address: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            pec: false,
+            timeout: Some(Microseconds(25_000)),
+            extended_timeout: None,
+            host_notify: false,
+            default_address: false,
+        }
+    }
+}
+
+impl Config {
+    /// Enables packet error checking
+    pub fn pec(mut self, enable: bool) -> Self {
+        self.pec = enable;
+        self
+    }
+
+    /// Sets the clock low timeout (tTIMEOUT, 25 ms by default)
+    pub fn timeout(mut self, timeout: Option<Microseconds>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the cumulative clock low extend timeout (tLOW:MEXT or tLOW:SEXT)
+    pub fn extended_timeout(mut self, timeout: Option<Microseconds>) -> Self {
+        self.extended_timeout = timeout;
+        self
+    }
+
+    /// Acknowledges the host address, to receive Host Notify messages
+    ///
+    /// Only used in host mode.
+    pub fn host_notify(mut self, enable: bool) -> Self {
+        self.host_notify = enable;
+        self
+    }
+
+    /// Acknowledges the SMBus device default address (0b110_0001)
+    ///
+    /// Only used in device mode.
+    pub fn default_address(mut self, enable: bool) -> Self {
+        self.default_address = enable;
+        self
+    }
+}
+
+/// Trait to mark SMBA (SMBus alert) pins
+pub trait SMBAPin<I2C> {
+    fn setup(&self);
+}
+
+/// Converts a timeout to a TIMEOUTA/TIMEOUTB value
+///
+/// The timeouts count in units of 2048 kernel clock cycles.
+fn timeout_bits(timeout: Microseconds, i2cclk: Hertz) -> Result<u16, InvalidConfig> {
+    let ticks = u64::from(timeout.0) * u64::from(i2cclk.0) / 2048 / 1_000_000;
+    if ticks == 0 || ticks > 0x1000 {
+        return Err(InvalidConfig);
+    }
+    Ok(ticks as u16 - 1)
+}
+
+/// Writes the SMBus configuration, with the peripheral disabled
+pub(super) fn configure(
+    i2c: &RegisterBlock,
+    config: &Config,
+    host: bool,
+    i2cclk: Hertz,
+) -> Result<(), InvalidConfig> {
+    let timeout = config
+        .timeout
+        .map(|timeout| timeout_bits(timeout, i2cclk))
+        .transpose()?;
+    let extended_timeout = config
+        .extended_timeout
+        .map(|timeout| timeout_bits(timeout, i2cclk))
+        .transpose()?;
+    // A device needs to stretch the clock, to control the byte count for the PEC
+    if !host && config.pec && i2c.cr1.read().nostretch().bit_is_set() {
+        return Err(InvalidConfig);
+    }
+
+    i2c.cr1.modify(|_, w| w.pe().clear_bit());
+
+    i2c.timeoutr.write(|w| {
+        if let Some(bits) = timeout {
+            w.timeouta().bits(bits);
+            w.timouten().set_bit();
+        }
+        if let Some(bits) = extended_timeout {
+            w.timeoutb().bits(bits);
+            w.texten().set_bit();
+        }
+        w
+    });
+
+    i2c.cr1.modify(|_, w| {
+        w.pecen().bit(config.pec);
+        w.smbhen().bit(host && config.host_notify);
+        w.smbden().bit(!host && config.default_address);
+        w.sbc().bit(!host && config.pec);
+        w.pe().set_bit()
+    });
+
+    Ok(())
+}
+
+/// SMBus host
+pub struct Smbus<I, SDA, SCL> {
+    i2c: I2c<I, SDA, SCL>,
+    pec: bool,
+}
+
+impl<I, SDA, SCL> Smbus<I, SDA, SCL>
+where
+    I: Instance,
+{
+    pub fn new(i2c: I2c<I, SDA, SCL>, config: Config, rcc: &Rcc) -> Result<Self, InvalidConfig> {
+        configure(&i2c.i2c, &config, true, I::kernel_clk(rcc))?;
+
+        Ok(Smbus {
+            i2c,
+            pec: config.pec,
+        })
+    }
+
+    /// Disables SMBus mode and returns the `I2c`
+    pub fn release(self) -> I2c<I, SDA, SCL> {
+        let i2c = &self.i2c.i2c;
+        i2c.cr1.modify(|_, w| w.pe().clear_bit());
+        i2c.timeoutr.reset();
+        i2c.cr1.modify(|_, w| {
+            w.pecen().clear_bit();
+            w.smbhen().clear_bit();
+            w.alerten().clear_bit();
+            w.pe().set_bit()
+        });
+        self.i2c
+    }
+
+    /// Sends a byte without a command code
+    pub fn send_byte(&mut self, address: u8, byte: u8) -> Result<(), Error> {
+        self.write(address, &[byte])
+    }
+
+    /// Receives a byte without a command code
+    pub fn receive_byte(&mut self, address: u8) -> Result<u8, Error> {
+        let mut buffer = [0];
+        self.write_read(address, &[], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    pub fn write_byte(&mut self, address: u8, command: u8, byte: u8) -> Result<(), Error> {
+        self.write(address, &[command, byte])
+    }
+
+    pub fn read_byte(&mut self, address: u8, command: u8) -> Result<u8, Error> {
+        let mut buffer = [0];
+        self.write_read(address, &[command], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    pub fn write_word(&mut self, address: u8, command: u8, word: u16) -> Result<(), Error> {
+        let [low, high] = word.to_le_bytes();
+        self.write(address, &[command, low, high])
+    }
+
+    pub fn read_word(&mut self, address: u8, command: u8) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.write_read(address, &[command], &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    /// Writes a block of data, preceded by its length
+    ///
+    /// Returns `Error::BlockSize`, if `data` is longer than 32 bytes.
+    pub fn block_write(&mut self, address: u8, command: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > BLOCK_MAX {
+            return Err(Error::BlockSize);
+        }
+
+        self.wait_idle()?;
+        self.start(
+            address,
+            2 + data.len(),
+            RD_WRN_A::Write,
+            AUTOEND_A::Automatic,
+        );
+        self.i2c.send_byte(command)?;
+        self.i2c.send_byte(data.len() as u8)?;
+        for &byte in data {
+            self.i2c.send_byte(byte)?;
+        }
+        self.wait_stop()
+    }
+
+    /// Reads a block of data, and returns its length
+    ///
+    /// Returns `Error::Overrun`, if the device sends more data than fits into
+    /// `buffer`. The excess data is discarded. Returns `Error::BlockSize`, if
+    /// the device announces no data, or more than 32 bytes.
+    pub fn block_read(
+        &mut self,
+        address: u8,
+        command: u8,
+        buffer: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.wait_idle()?;
+        self.start(address, 1, RD_WRN_A::Write, AUTOEND_A::Software);
+        self.i2c.send_byte(command)?;
+        self.wait_transfer_complete()?;
+
+        // The length isn't known yet, so receive the count byte in reload
+        // mode, then continue with the actual number of bytes
+        self.start_reload(address);
+        let count = usize::from(self.i2c.recv_byte()?);
+        while self.i2c.i2c.isr.read().tcr().bit_is_clear() {
+            self.i2c.check_errors()?;
+        }
+
+        // SMBus blocks contain 1 to 32 bytes. A count of 0 can't be handled
+        // like the others: writing NBYTES = 0 without PEC doesn't clear TCR,
+        // which would stretch SCL forever instead of sending a STOP.
+        if count == 0 || count > BLOCK_MAX {
+            // End the transfer after one more byte
+            self.i2c.i2c.cr2.modify(|_, w| {
+                w.nbytes().bits(1);
+                w.reload().clear_bit();
+                w.autoend().set_bit();
+                w.pecbyte().clear_bit()
+            });
+            self.i2c.recv_byte()?;
+            self.wait_stop()?;
+            return Err(Error::BlockSize);
+        }
+
+        let nbytes = (count + usize::from(self.pec)) as u8;
+        self.i2c.i2c.cr2.modify(|_, w| {
+            w.nbytes().bits(nbytes);
+            w.reload().clear_bit();
+            w.autoend().set_bit();
+            w.pecbyte().bit(self.pec)
+        });
+
+        for i in 0..count {
+            let byte = self.i2c.recv_byte()?;
+            if let Some(slot) = buffer.get_mut(i) {
+                *slot = byte;
+            }
+        }
+        if self.pec {
+            // The PEC byte is checked by the hardware
+            self.i2c.recv_byte()?;
+        }
+        self.wait_stop()?;
+
+        if count > buffer.len() {
+            return Err(Error::Overrun);
+        }
+        Ok(count)
+    }
+
+    /// Enables detection of alerts on SMBA
+    ///
+    /// Pending alerts are reported by `is_alert`. If the I2C error interrupt is
+    /// enabled, alerts trigger it too.
+    pub fn enable_alert<P>(&mut self, pin: &P)
+    where
+        P: SMBAPin<I>,
+    {
+        pin.setup();
+        self.i2c.i2c.cr1.modify(|_, w| w.alerten().set_bit());
+    }
+
+    pub fn disable_alert(&mut self) {
+        self.i2c.i2c.cr1.modify(|_, w| w.alerten().clear_bit());
+    }
+
+    /// Returns true, if a device has signaled an alert
+    ///
+    /// Use the alert response address (0b000_1100) with `receive_byte` to find
+    /// the device.
+    pub fn is_alert(&self) -> bool {
+        self.i2c.i2c.isr.read().alert().bit_is_set()
+    }
+
+    pub fn clear_alert(&mut self) {
+        self.i2c.i2c.icr.write(|w| w.alertcf().set_bit());
+    }
+
+    /// Receives a Host Notify message
+    ///
+    /// Requires `Config::host_notify`. Returns the address of the notifying
+    /// device and its status word.
+    pub fn host_notify(&mut self) -> nb::Result<(u8, u16), Error> {
+        self.i2c.check_errors()?;
+
+        let isr = self.i2c.i2c.isr.read();
+        if isr.addr().bit_is_clear() || isr.addcode().bits() != HOST_ADDRESS {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.i2c.i2c.icr.write(|w| w.addrcf().set_bit());
+
+        let address = self.i2c.recv_byte()? >> 1;
+        let low = self.i2c.recv_byte()?;
+        let high = self.i2c.recv_byte()?;
+        self.wait_stop()?;
+
+        Ok((address, u16::from_le_bytes([low, high])))
+    }
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.wait_idle()?;
+        self.start(address, bytes.len(), RD_WRN_A::Write, AUTOEND_A::Automatic);
+        for &byte in bytes {
+            self.i2c.send_byte(byte)?;
+        }
+        self.wait_stop()
+    }
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.wait_idle()?;
+        if !bytes.is_empty() {
+            self.start(address, bytes.len(), RD_WRN_A::Write, AUTOEND_A::Software);
+            for &byte in bytes {
+                self.i2c.send_byte(byte)?;
+            }
+            self.wait_transfer_complete()?;
+        }
+
+        self.start(address, buffer.len(), RD_WRN_A::Read, AUTOEND_A::Automatic);
+        for byte in buffer {
+            *byte = self.i2c.recv_byte()?;
+        }
+        if self.pec {
+            // The PEC byte is checked by the hardware
+            self.i2c.recv_byte()?;
+        }
+        self.wait_stop()
+    }
+
+    /// Starts a transfer of `len` bytes
+    ///
+    /// With automatic end mode, the transfer ends with the PEC byte, if PEC is
+    /// enabled.
+    fn start(&mut self, address: u8, len: usize, direction: RD_WRN_A, autoend: AUTOEND_A) {
+        let pec = self.pec && autoend == AUTOEND_A::Automatic;
+        let len = len + usize::from(pec);
+
+        // Ensure that TX/RX buffers are empty, and no stale stop is pending
+        let i2c = &self.i2c.i2c;
+        i2c.isr.write(|w| w.txe().set_bit());
+        while i2c.isr.read().rxne().bit_is_set() {
+            i2c.rxdr.read();
+        }
+        i2c.icr.write(|w| w.stopcf().set_bit());
+
+        i2c.cr2.write(|w| {
+            w.start().set_bit();
+            w.nbytes().bits(len as u8);
+            w.sadd().bits((address << 1) as u16);
+            w.rd_wrn().variant(direction);
+            w.pecbyte().bit(pec);
+            w.autoend().variant(autoend)
+        });
+    }
+
+    /// Starts a read of one byte in reload mode
+    fn start_reload(&mut self, address: u8) {
+        self.i2c.i2c.cr2.write(|w| {
+            w.start().set_bit();
+            w.nbytes().bits(1);
+            w.sadd().bits((address << 1) as u16);
+            w.rd_wrn().variant(RD_WRN_A::Read);
+            w.reload().set_bit()
+        });
+    }
+
+    fn wait_idle(&self) -> Result<(), Error> {
+        while self.i2c.i2c.isr.read().busy().is_busy() {
+            self.i2c.check_errors()?;
+        }
+        Ok(())
+    }
+
+    fn wait_transfer_complete(&self) -> Result<(), Error> {
+        while self.i2c.i2c.isr.read().tc().is_not_complete() {
+            self.i2c.check_errors()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the stop condition, reporting NACK and PEC errors
+    fn wait_stop(&self) -> Result<(), Error> {
+        while self.i2c.i2c.isr.read().stopf().bit_is_clear() {
+            self.i2c.check_errors()?;
+        }
+        self.i2c.i2c.icr.write(|w| w.stopcf().set_bit());
+        self.i2c.check_errors()
+    }
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+impl SMBAPin<I2C1> for PB5<Output<OpenDrain>> {
+    fn setup(&self) {
+        self.set_alt_mode(AltMode::AF3)
+    }
+}
@@ -0,0 +1,730 @@
+//! I2C target (slave) mode
+//!
+//! The peripheral answers to one or two own addresses, and optionally to the
+//! general call address. Transfers are driven by the controller on the bus:
+//! call `I2cTarget::event` (from the I2C interrupt handler, or by polling)
+//! until it reports an address match, then exchange data with `read`/`write`,
+//! byte by byte, or using DMA.
+
+#[cfg(feature = "stm32l0x2")]
+use core::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+};
+
+#[cfg(feature = "stm32l0x2")]
+use as_slice::{AsMutSlice, AsSlice};
+use embedded_time::rate::Hertz;
+
+use super::Config as BusConfig;
+use super::{smbus, Error, Instance, InvalidConfig, SCLPin, SDAPin, Timing};
+#[cfg(feature = "stm32l0x2")]
+use super::{Rx, Transfer, Tx};
+#[cfg(feature = "stm32l0x2")]
+use crate::dma::{self, Buffer};
+use crate::rcc::{Rcc, HSI_FREQ};
+use crate::syscfg::SYSCFG;
+
+#[cfg(any(
+    feature = "io-STM32L021",
+    feature = "io-STM32L031",
+    feature = "io-STM32L051",
+    feature = "io-STM32L071",
+))]
+use crate::pac::I2C1;
+#[cfg(feature = "io-STM32L071")]
+use crate::pac::I2C3;
+
+/// Own address of the target
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+/// Target mode configuration
+#[derive(Clone, Copy)]
+pub struct Config {
+    address: Address,
+    secondary_address: Option<(u8, u8)>,
+    general_call: bool,
+    clock_stretching: bool,
+}
+
+impl Config {
+    pub fn new(address: Address) -> Self {
+        Config {
+            address,
+            secondary_address: None,
+            general_call: false,
+            clock_stretching: true,
+        }
+    }
+
+    /// Also answers to the 7-bit `address`, ignoring its `masked_bits` LSBs
+    ///
+    /// `masked_bits` must be at most 7. With 7, all addresses except the
+    /// reserved ones are acknowledged.
+    pub fn secondary_address(mut self, address: u8, masked_bits: u8) -> Self {
+        self.secondary_address = Some((address, masked_bits));
+        self
+    }
+
+    /// Answers to the general call address (0)
+    pub fn general_call(mut self, enable: bool) -> Self {
+        self.general_call = enable;
+        self
+    }
+
+    /// Stretches the clock while waiting for the software
+    ///
+    /// Enabled by default. Without clock stretching, the software must keep up
+    /// with the bus, or `Error::Overrun` is reported.
+    pub fn clock_stretching(mut self, enable: bool) -> Self {
+        self.clock_stretching = enable;
+        self
+    }
+}
+
+/// Transfer direction, as seen from the controller
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// The controller writes, the target receives
+    Write,
+    /// The controller reads, the target transmits
+    Read,
+}
+
+/// Events reported by `I2cTarget::event`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TargetEvent {
+    /// The target has been addressed
+    ///
+    /// `address` is the matched address: one of the own addresses, an
+    /// address matched by the secondary address mask, or 0 for a general call.
+    AddressMatch { address: u16, direction: Direction },
+    /// The controller didn't acknowledge a transmitted byte
+    ///
+    /// This is how the controller ends a read.
+    Nack,
+    /// A stop condition has been detected
+    Stop,
+}
+
+/// Interrupt events
+pub enum Event {
+    /// Address matched (ADDR)
+    AddressMatch,
+    /// Received byte available (RXNE)
+    Rxne,
+    /// Transmit register empty, in target transmitter mode (TXIS)
+    Txis,
+    /// NACK received (NACKF)
+    Nack,
+    /// Stop condition detected (STOPF)
+    Stop,
+    /// Bus error, arbitration loss, overrun, and SMBus errors and alerts
+    Error,
+}
+
+/// I2C peripheral in target mode
+pub struct I2cTarget<I2C, SDA, SCL> {
+    i2c: I2C,
+    sda: SDA,
+    scl: SCL,
+    bus_config: BusConfig,
+    i2cclk: Hertz,
+}
+
+impl<I, SDA, SCL> I2cTarget<I, SDA, SCL>
+where
+    I: Instance,
+{
+    /// Configures the peripheral as a target
+    ///
+    /// `bus_config` describes the bus, as used by the controller. It
+    /// determines the data setup and hold times, and the noise filters. Bus
+    /// frequencies above 400 kHz require `new_fast_mode_plus`, and are rejected.
+    pub fn new<C>(
+        i2c: I,
+        sda: SDA,
+        scl: SCL,
+        config: Config,
+        bus_config: C,
+        rcc: &mut Rcc,
+    ) -> Result<Self, InvalidConfig>
+    where
+        SDA: SDAPin<I>,
+        SCL: SCLPin<I>,
+        C: Into<BusConfig>,
+    {
+        let bus_config = bus_config.into();
+        if bus_config.is_fast_mode_plus() {
+            return Err(InvalidConfig);
+        }
+
+        Self::init(i2c, sda, scl, config, bus_config, rcc)
+    }
+
+    /// Configures the peripheral as a target, with the Fast-mode Plus drive
+    /// capability of the SDA and SCL pins enabled
+    ///
+    /// Required for bus frequencies above 400 kHz.
+    pub fn new_fast_mode_plus<C>(
+        i2c: I,
+        sda: SDA,
+        scl: SCL,
+        config: Config,
+        bus_config: C,
+        syscfg: &mut SYSCFG,
+        rcc: &mut Rcc,
+    ) -> Result<Self, InvalidConfig>
+    where
+        SDA: SDAPin<I>,
+        SCL: SCLPin<I>,
+        C: Into<BusConfig>,
+    {
+        let target = Self::init(i2c, sda, scl, config, bus_config.into(), rcc)?;
+        I::enable_fast_mode_plus(syscfg);
+
+        Ok(target)
+    }
+
+    fn init(
+        i2c: I,
+        sda: SDA,
+        scl: SCL,
+        config: Config,
+        bus_config: BusConfig,
+        rcc: &mut Rcc,
+    ) -> Result<Self, InvalidConfig>
+    where
+        SDA: SDAPin<I>,
+        SCL: SCLPin<I>,
+    {
+        let i2cclk = I::kernel_clk(rcc);
+        let timing = Timing::calculate(i2cclk, &bus_config)?;
+
+        match config.address {
+            Address::SevenBit(address) if address > 0x7f => return Err(InvalidConfig),
+            Address::TenBit(address) if address > 0x3ff => return Err(InvalidConfig),
+            _ => {}
+        }
+        if let Some((address, masked_bits)) = config.secondary_address {
+            if address > 0x7f || masked_bits > 7 {
+                return Err(InvalidConfig);
+            }
+        }
+
+        sda.setup();
+        scl.setup();
+
+        // Enable clock for I2C
+        I::enable(rcc);
+        // Reset I2C
+        I::reset(rcc);
+
+        timing.write(&i2c, &bus_config);
+
+        i2c.oar1.write(|w| {
+            match config.address {
+                Address::SevenBit(address) => w.oa1().bits(u16::from(address) << 1),
+                Address::TenBit(address) => w.oa1().bits(address).oa1mode().set_bit(),
+            };
+            w.oa1en().set_bit()
+        });
+        if let Some((address, masked_bits)) = config.secondary_address {
+            i2c.oar2.write(|w| {
+                w.oa2().bits(address);
+                w.oa2msk().bits(masked_bits);
+                w.oa2en().set_bit()
+            });
+        }
+
+        i2c.cr1.modify(|_, w| {
+            w.gcen().bit(config.general_call);
+            w.nostretch().bit(!config.clock_stretching);
+            // Enable peripheral
+            w.pe().set_bit()
+        });
+
+        Ok(I2cTarget {
+            i2c,
+            sda,
+            scl,
+            bus_config,
+            i2cclk,
+        })
+    }
+
+    /// Disables the peripheral and returns it, along with the pins
+    pub fn release(self) -> (I, SDA, SCL) {
+        self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+        (self.i2c, self.sda, self.scl)
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        self.i2c.cr1.modify(|_, w| match event {
+            Event::AddressMatch => w.addrie().set_bit(),
+            Event::Rxne => w.rxie().set_bit(),
+            Event::Txis => w.txie().set_bit(),
+            Event::Nack => w.nackie().set_bit(),
+            Event::Stop => w.stopie().set_bit(),
+            Event::Error => w.errie().set_bit(),
+        });
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.i2c.cr1.modify(|_, w| match event {
+            Event::AddressMatch => w.addrie().clear_bit(),
+            Event::Rxne => w.rxie().clear_bit(),
+            Event::Txis => w.txie().clear_bit(),
+            Event::Nack => w.nackie().clear_bit(),
+            Event::Stop => w.stopie().clear_bit(),
+            Event::Error => w.errie().clear_bit(),
+        });
+    }
+
+    /// Returns and clears the next pending event
+    ///
+    /// On an address match in the `Read` direction, the transmit register is
+    /// flushed, so data from an earlier transfer isn't sent. With clock
+    /// stretching enabled, the bus is held until the event has been handled.
+    pub fn event(&mut self) -> nb::Result<TargetEvent, Error> {
+        self.check_errors()?;
+
+        let isr = self.i2c.isr.read();
+        // Report the end of the previous transfer before a new address match
+        if isr.nackf().bit_is_set() {
+            self.i2c.icr.write(|w| w.nackcf().set_bit());
+            return Ok(TargetEvent::Nack);
+        }
+        if isr.stopf().bit_is_set() {
+            self.i2c.icr.write(|w| w.stopcf().set_bit());
+            return Ok(TargetEvent::Stop);
+        }
+        if isr.addr().bit_is_set() {
+            let direction = if isr.dir().bit_is_set() {
+                Direction::Read
+            } else {
+                Direction::Write
+            };
+            if direction == Direction::Read {
+                self.i2c.isr.write(|w| w.txe().set_bit());
+            }
+            if self.is_pec_enabled() {
+                // Transfer byte by byte, so the PEC can be placed after the data
+                self.next_byte(false);
+            }
+            let address = self.matched_address(isr.addcode().bits());
+            self.i2c.icr.write(|w| w.addrcf().set_bit());
+            return Ok(TargetEvent::AddressMatch { address, direction });
+        }
+
+        Err(nb::Error::WouldBlock)
+    }
+
+    /// Reads a received byte
+    pub fn read_byte(&mut self) -> nb::Result<u8, Error> {
+        self.check_errors()?;
+
+        if self.i2c.isr.read().rxne().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(self.i2c.rxdr.read().rxdata().bits())
+    }
+
+    /// Writes a byte to be transmitted
+    pub fn write_byte(&mut self, byte: u8) -> nb::Result<(), Error> {
+        self.check_errors()?;
+
+        if self.i2c.isr.read().txis().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.i2c.txdr.write(|w| w.txdata().bits(byte));
+        Ok(())
+    }
+
+    /// Receives data written by the controller
+    ///
+    /// Call this after an address match in the `Write` direction. Returns the
+    /// number of received bytes, once the controller sends a stop condition,
+    /// or a repeated start. Bytes that don't fit into `buffer` are discarded,
+    /// and `Error::Overrun` is returned.
+    ///
+    /// With SMBus PEC enabled, the PEC is expected after `buffer.len()` bytes,
+    /// and checked by the hardware. A wrong PEC is NACKed, and reported as
+    /// `Error::PECError`.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut received = 0;
+        let mut overrun = false;
+        let mut pec_next = false;
+
+        loop {
+            self.check_errors()?;
+
+            let isr = self.i2c.isr.read();
+            if isr.rxne().bit_is_set() {
+                let byte = self.i2c.rxdr.read().rxdata().bits();
+                if pec_next {
+                    // The PEC has already been checked by the hardware
+                    continue;
+                }
+                match buffer.get_mut(received) {
+                    Some(slot) => {
+                        *slot = byte;
+                        received += 1;
+                    }
+                    None => overrun = true,
+                }
+            } else if isr.tcr().bit_is_set() {
+                pec_next = received == buffer.len();
+                self.next_byte(pec_next);
+            } else if isr.stopf().bit_is_set() {
+                self.i2c.icr.write(|w| w.stopcf().set_bit());
+                break;
+            } else if isr.addr().bit_is_set() {
+                // Repeated start, left for the next call to `event`
+                break;
+            }
+        }
+
+        if overrun {
+            return Err(Error::Overrun);
+        }
+        Ok(received)
+    }
+
+    /// Transmits data read by the controller
+    ///
+    /// Call this after an address match in the `Read` direction. If the
+    /// controller reads more than `bytes`, 0xff is sent. Returns the number
+    /// of bytes from `bytes` that have been sent, once the controller ends the
+    /// transfer.
+    ///
+    /// With SMBus PEC enabled, the PEC is sent by the hardware after `bytes`.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<usize, Error> {
+        let mut written = 0;
+
+        loop {
+            self.check_errors()?;
+
+            let isr = self.i2c.isr.read();
+            if isr.txis().bit_is_set() {
+                let byte = bytes.get(written).copied().unwrap_or(0xff);
+                self.i2c.txdr.write(|w| w.txdata().bits(byte));
+                written += 1;
+            } else if isr.tcr().bit_is_set() {
+                self.next_byte(written >= bytes.len());
+            } else if isr.nackf().bit_is_set() {
+                self.i2c.icr.write(|w| w.nackcf().set_bit());
+            } else if isr.stopf().bit_is_set() {
+                self.i2c.icr.write(|w| w.stopcf().set_bit());
+                break;
+            } else if isr.addr().bit_is_set() {
+                // Repeated start, left for the next call to `event`
+                break;
+            }
+        }
+
+        // The last byte written hasn't been sent, if it's still in TXDR
+        if self.i2c.isr.read().txe().bit_is_clear() {
+            written = written.saturating_sub(1);
+            self.i2c.isr.write(|w| w.txe().set_bit());
+        }
+        Ok(written.min(bytes.len()))
+    }
+
+    /// Receives data written by the controller, using DMA
+    ///
+    /// Start the transfer after an address match in the `Write` direction.
+    /// As the controller decides how many bytes are written, poll
+    /// `Transfer::event` for the stop condition, and end the transfer with
+    /// `Transfer::finish`.
+    #[cfg(feature = "stm32l0x2")]
+    pub fn read_dma<Channel, Buffer>(
+        self,
+        dma: &mut dma::Handle,
+        channel: Channel,
+        buffer: Pin<Buffer>,
+    ) -> Transfer<Self, Rx<I>, Channel, Buffer, dma::Ready>
+    where
+        Rx<I>: dma::Target<Channel>,
+        Channel: dma::Channel,
+        Buffer: DerefMut + 'static,
+        Buffer::Target: AsMutSlice<Element = u8>,
+    {
+        self.i2c.cr1.modify(|_, w| w.rxdmaen().set_bit());
+
+        // See explanation of tokens in `I2c::write_all`.
+        let token = Rx(PhantomData);
+
+        // Safe, because we're only taking the address of a register.
+        let address = &unsafe { &*I::ptr() }.rxdr as *const _ as u32;
+
+        let num_words = buffer.len();
+        // Safe, because the trait bounds of this method guarantee that the
+        // buffer can be written to.
+        let transfer = unsafe {
+            dma::Transfer::new(
+                dma,
+                token,
+                channel,
+                buffer,
+                num_words,
+                address,
+                dma::Priority::high(),
+                dma::Direction::peripheral_to_memory(),
+                false,
+            )
+        };
+
+        Transfer {
+            target: self,
+            inner: transfer,
+        }
+    }
+
+    /// Transmits data read by the controller, using DMA
+    ///
+    /// Start the transfer after an address match in the `Read` direction. Poll
+    /// `Transfer::event` for the NACK of the controller, and end the transfer
+    /// with `Transfer::finish`.
+    #[cfg(feature = "stm32l0x2")]
+    pub fn write_dma<Channel, Buffer>(
+        self,
+        dma: &mut dma::Handle,
+        channel: Channel,
+        buffer: Pin<Buffer>,
+    ) -> Transfer<Self, Tx<I>, Channel, Buffer, dma::Ready>
+    where
+        Tx<I>: dma::Target<Channel>,
+        Channel: dma::Channel,
+        Buffer: Deref + 'static,
+        Buffer::Target: AsSlice<Element = u8>,
+    {
+        self.i2c.cr1.modify(|_, w| w.txdmaen().set_bit());
+
+        // See explanation of tokens in `I2c::write_all`.
+        let token = Tx(PhantomData);
+
+        // Safe, because we're only taking the address of a register.
+        let address = &unsafe { &*I::ptr() }.txdr as *const _ as u32;
+
+        let num_words = buffer.len();
+        // Safe, because the trait bounds of this method guarantee that the
+        // buffer can be read from.
+        let transfer = unsafe {
+            dma::Transfer::new(
+                dma,
+                token,
+                channel,
+                buffer,
+                num_words,
+                address,
+                dma::Priority::high(),
+                dma::Direction::memory_to_peripheral(),
+                false,
+            )
+        };
+
+        Transfer {
+            target: self,
+            inner: transfer,
+        }
+    }
+
+    /// Enables SMBus device mode
+    ///
+    /// If `enable_wakeup` is used, call it first, as the timeouts depend on
+    /// the kernel clock. `Config::host_notify` is ignored.
+    ///
+    /// With PEC enabled, the hardware sends and checks the PEC, and controls
+    /// the transfer byte by byte to do so. This requires clock stretching, and
+    /// is only handled by `read` and `write`, not by the byte-wise and DMA
+    /// methods. Returns `InvalidConfig`, if clock stretching is disabled.
+    pub fn enable_smbus(&mut self, config: smbus::Config) -> Result<(), InvalidConfig> {
+        smbus::configure(&self.i2c, &config, false, self.i2cclk)
+    }
+
+    /// Signals an alert to the SMBus host, by driving SMBA low
+    pub fn enable_alert<P>(&mut self, pin: &P)
+    where
+        P: smbus::SMBAPin<I>,
+    {
+        pin.setup();
+        self.i2c.cr1.modify(|_, w| w.alerten().set_bit());
+    }
+
+    /// Releases SMBA
+    pub fn disable_alert(&mut self) {
+        self.i2c.cr1.modify(|_, w| w.alerten().clear_bit());
+    }
+
+    /// Returns true, if SMBus PEC is enabled
+    ///
+    /// Slave byte control is enabled together with the PEC.
+    fn is_pec_enabled(&self) -> bool {
+        self.i2c.cr1.read().sbc().bit_is_set()
+    }
+
+    /// Transfers one more byte under slave byte control, which is the PEC if
+    /// `pec` is true
+    fn next_byte(&self, pec: bool) {
+        self.i2c.cr2.modify(|_, w| {
+            w.nbytes().bits(1);
+            w.reload().bit(!pec);
+            w.pecbyte().bit(pec)
+        });
+    }
+
+    fn check_errors(&self) -> Result<(), Error> {
+        let isr = self.i2c.isr.read();
+        if isr.berr().bit_is_set() {
+            self.i2c.icr.write(|w| w.berrcf().set_bit());
+            Err(Error::BusError)
+        } else if isr.arlo().bit_is_set() {
+            self.i2c.icr.write(|w| w.arlocf().set_bit());
+            Err(Error::ArbitrationLost)
+        } else if isr.ovr().bit_is_set() {
+            self.i2c.icr.write(|w| w.ovrcf().set_bit());
+            Err(Error::Overrun)
+        } else if isr.pecerr().bit_is_set() {
+            self.i2c.icr.write(|w| w.peccf().set_bit());
+            Err(Error::PECError)
+        } else if isr.timeout().bit_is_set() {
+            self.i2c.icr.write(|w| w.timoutcf().set_bit());
+            Err(Error::Timeout)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn matched_address(&self, addcode: u8) -> u16 {
+        let oar1 = self.i2c.oar1.read();
+        if oar1.oa1mode().bit_is_set() {
+            // ADDCODE only holds the 10-bit header, 0b11110xx
+            let address = oar1.oa1().bits();
+            if u16::from(addcode) == 0b111_1000 | address >> 8 {
+                return address;
+            }
+        }
+        u16::from(addcode)
+    }
+}
+
+#[cfg(feature = "stm32l0x2")]
+impl<I, SDA, SCL, Token, Channel, Buffer>
+    Transfer<I2cTarget<I, SDA, SCL>, Token, Channel, Buffer, dma::Started>
+where
+    I: Instance,
+    Channel: dma::Channel,
+{
+    /// Returns and clears the next pending event of the target
+    ///
+    /// See `I2cTarget::event`. A `TargetEvent::Stop`, or a `TargetEvent::Nack`
+    /// when transmitting, signals the end of the transfer.
+    pub fn event(&mut self) -> nb::Result<TargetEvent, Error> {
+        self.target.event()
+    }
+
+    /// Ends the transfer, when the controller is done
+    ///
+    /// Stops the DMA transfer and disables the DMA requests. A byte that has
+    /// been loaded into the transmit register, but not read by the controller,
+    /// is discarded. Returns the owned resources, along with the number of
+    /// bytes that have not been transferred.
+    pub fn finish(
+        self,
+    ) -> (
+        dma::TransferResources<I2cTarget<I, SDA, SCL>, Channel, Buffer>,
+        u16,
+    ) {
+        let (res, remaining) = self.stop();
+
+        let i2c = &res.target.i2c;
+        i2c.cr1
+            .modify(|_, w| w.rxdmaen().clear_bit().txdmaen().clear_bit());
+        i2c.isr.write(|w| w.txe().set_bit());
+        i2c.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+
+        (res, remaining)
+    }
+}
+
+macro_rules! i2c_wakeup {
+    ($($I2CX:ident: $i2cXsel:ident,)+) => {
+        $(
+            impl<SDA, SCL> I2cTarget<$I2CX, SDA, SCL> {
+                /// Enables waking up the MCU from Stop mode on an address match
+                ///
+                /// Switches the kernel clock of the peripheral to HSI16, and keeps
+                /// that clock available in Stop mode. The timing is recalculated
+                /// for the new kernel clock.
+                ///
+                /// To actually wake up, listen for `Event::AddressMatch`, and
+                /// enable the matching `exti::DirectLine`, e.g. by using
+                /// `Exti::wait_for_irq` together with `PWR::stop_mode`. The clock
+                /// is stretched until the MCU has woken up.
+                ///
+                /// Returns `InvalidConfig`, if clock stretching is disabled or the
+                /// digital filter is enabled, as waking up doesn't work then, or if
+                /// the bus timing can't be met with HSI16.
+                pub fn enable_wakeup(&mut self, rcc: &mut Rcc) -> Result<(), InvalidConfig> {
+                    if self.i2c.cr1.read().nostretch().bit_is_set()
+                        || self.bus_config.digital_filter != 0
+                    {
+                        return Err(InvalidConfig);
+                    }
+                    let timing = Timing::calculate(Hertz(HSI_FREQ), &self.bus_config)?;
+
+                    // The timing can only be written while the peripheral is
+                    // disabled
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+
+                    // Enable HSI16 and keep it available to the kernel in Stop
+                    // mode
+                    rcc.rb
+                        .cr
+                        .modify(|_, w| w.hsi16on().set_bit().hsi16keron().set_bit());
+                    while rcc.rb.cr.read().hsi16rdyf().bit_is_clear() {}
+                    rcc.rb.ccipr.modify(|_, w| w.$i2cXsel().hsi16());
+
+                    self.i2cclk = Hertz(HSI_FREQ);
+                    timing.write(&self.i2c, &self.bus_config);
+
+                    self.i2c
+                        .cr1
+                        .modify(|_, w| w.wupen().set_bit().pe().set_bit());
+
+                    Ok(())
+                }
+
+                /// Disables waking up the MCU from Stop mode
+                ///
+                /// The kernel clock selected by `enable_wakeup` stays active.
+                pub fn disable_wakeup(&mut self) {
+                    self.i2c.cr1.modify(|_, w| w.wupen().clear_bit());
+                }
+            }
+        )+
+    }
+}
+
+#[cfg(any(
+    feature = "io-STM32L021",
+    feature = "io-STM32L031",
+    feature = "io-STM32L051",
+    feature = "io-STM32L071",
+))]
+i2c_wakeup! {
+    I2C1: i2c1sel,
+}
+
+#[cfg(feature = "io-STM32L071")]
+i2c_wakeup! {
+    I2C3: i2c3sel,
+}
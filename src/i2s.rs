@@ -0,0 +1,506 @@
+//! Inter-IC Sound (I2S) interface
+//!
+//! I2S is supported by SPI2, which is used exclusively by this driver. Data is
+//! exchanged as 16-bit words. With 24 or 32 bit data, each sample consists of
+//! two words, most significant half first. Samples alternate between the left
+//! and the right channel, starting with the left one.
+
+use core::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    ptr,
+};
+
+use as_slice::{AsMutSlice, AsSlice};
+use embedded_time::rate::Hertz;
+
+use crate::dma::{self, Buffer};
+use crate::gpio::gpiob::*;
+use crate::gpio::{AltMode, Analog};
+use crate::pac::SPI2;
+use crate::rcc::{Enable, Rcc};
+use crate::spi::{Rx, Transfer, Tx};
+
+/// I2S error
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Received data was not read in time
+    Overrun,
+    /// No data was available when the slave had to transmit
+    Underrun,
+    /// The WS signal changed unexpectedly (slave mode)
+    FrameError,
+}
+
+/// The requested sample rate can't be derived from the system clock
+#[derive(Debug)]
+pub struct InvalidConfig;
+
+/// Operating mode
+pub enum Mode {
+    MasterTransmit,
+    MasterReceive,
+    SlaveTransmit,
+    SlaveReceive,
+}
+
+/// I2S standard
+pub enum Standard {
+    /// I2S Philips standard
+    Philips,
+    /// MSB justified (left justified)
+    MsbJustified,
+    /// LSB justified (right justified)
+    LsbJustified,
+    /// PCM, with a WS pulse of one clock cycle
+    PcmShortFrame,
+    /// PCM, with a WS pulse of 13 clock cycles
+    PcmLongFrame,
+}
+
+/// Data and channel length
+pub enum DataFormat {
+    /// 16 bit data in a 16 bit channel
+    Data16Channel16,
+    /// 16 bit data in a 32 bit channel
+    Data16Channel32,
+    /// 24 bit data in a 32 bit channel
+    Data24Channel32,
+    /// 32 bit data in a 32 bit channel
+    Data32Channel32,
+}
+
+/// I2S configuration
+pub struct Config {
+    pub mode: Mode,
+    pub standard: Standard,
+    pub data_format: DataFormat,
+    /// Sample rate, only used in master mode
+    pub sample_rate: Hertz,
+    /// The clock is high when idle
+    pub clock_polarity_high: bool,
+}
+
+impl Config {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn standard(mut self, standard: Standard) -> Self {
+        self.standard = standard;
+        self
+    }
+
+    pub fn data_format(mut self, data_format: DataFormat) -> Self {
+        self.data_format = data_format;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: impl Into<Hertz>) -> Self {
+        self.sample_rate = sample_rate.into();
+        self
+    }
+
+    pub fn clock_polarity_high(mut self, clock_polarity_high: bool) -> Self {
+        self.clock_polarity_high = clock_polarity_high;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: Mode::MasterTransmit,
+            standard: Standard::Philips,
+            data_format: DataFormat::Data16Channel16,
+            sample_rate: Hertz(48_000),
+            clock_polarity_high: false,
+        }
+    }
+}
+
+pub trait Pins {
+    /// Whether the pins include the master clock output
+    const MASTER_CLOCK: bool;
+
+    fn setup(&self);
+}
+pub trait PinCk {
+    fn setup(&self);
+}
+pub trait PinWs {
+    fn setup(&self);
+}
+pub trait PinSd {
+    fn setup(&self);
+}
+pub trait PinMck {
+    fn setup(&self);
+}
+
+impl<CK, WS, SD> Pins for (CK, WS, SD)
+where
+    CK: PinCk,
+    WS: PinWs,
+    SD: PinSd,
+{
+    const MASTER_CLOCK: bool = false;
+
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+        self.2.setup();
+    }
+}
+
+/// Pins including the master clock output, which runs at 256 times the sample
+/// rate
+impl<CK, WS, SD, MCK> Pins for (CK, WS, SD, MCK)
+where
+    CK: PinCk,
+    WS: PinWs,
+    SD: PinSd,
+    MCK: PinMck,
+{
+    const MASTER_CLOCK: bool = true;
+
+    fn setup(&self) {
+        self.0.setup();
+        self.1.setup();
+        self.2.setup();
+        self.3.setup();
+    }
+}
+
+macro_rules! pins {
+    ($($PIN:ty: [$($TRAIT:ident),*],)+) => {
+        $(
+            $(
+                impl $TRAIT for $PIN {
+                    fn setup(&self) {
+                        self.set_alt_mode(AltMode::AF0);
+                    }
+                }
+            )*
+        )+
+    }
+}
+
+pins! {
+    PB12<Analog>: [PinWs],
+    PB13<Analog>: [PinCk],
+    PB14<Analog>: [PinMck],
+    PB15<Analog>: [PinSd],
+}
+
+/// Computes the prescaler (I2SDIV, ODD) for the given sample rate
+fn prescaler(
+    i2s_clk: u32,
+    sample_rate: u32,
+    master_clock: bool,
+    channel_32: bool,
+) -> Result<(u8, bool), InvalidConfig> {
+    // Clock cycles per sample at the prescaler output
+    let cycles = match (master_clock, channel_32) {
+        (true, _) => 256,
+        (false, false) => 32,
+        (false, true) => 64,
+    };
+    let frame_clk = sample_rate.checked_mul(cycles).ok_or(InvalidConfig)?;
+    if frame_clk == 0 {
+        return Err(InvalidConfig);
+    }
+
+    // Round to the nearest divider of 2 * I2SDIV + ODD
+    let div = (i2s_clk + frame_clk / 2) / frame_clk;
+    let i2sdiv = div / 2;
+    if !(2..=0xff).contains(&i2sdiv) {
+        return Err(InvalidConfig);
+    }
+
+    Ok((i2sdiv as u8, div % 2 == 1))
+}
+
+/// I2S interface on SPI2
+pub struct I2s<PINS> {
+    spi: SPI2,
+    pins: PINS,
+}
+
+impl<PINS> I2s<PINS>
+where
+    PINS: Pins,
+{
+    /// Configures SPI2 for I2S and enables it
+    ///
+    /// The prescaler is computed from the system clock. Returns
+    /// `InvalidConfig`, if the sample rate can't be reached in master mode.
+    pub fn new(spi: SPI2, pins: PINS, config: Config, rcc: &mut Rcc) -> Result<Self, InvalidConfig> {
+        let master = matches!(config.mode, Mode::MasterTransmit | Mode::MasterReceive);
+        let channel_32 = !matches!(config.data_format, DataFormat::Data16Channel16);
+
+        let (i2sdiv, odd) = if master {
+            prescaler(
+                rcc.clocks.sys_clk().0,
+                config.sample_rate.0,
+                PINS::MASTER_CLOCK,
+                channel_32,
+            )?
+        } else {
+            // Reset values, the clock is provided by the master
+            (2, false)
+        };
+
+        pins.setup();
+
+        // Enable clock for SPI
+        <SPI2>::enable(rcc);
+
+        spi.cr2.write(|w| {
+            // enable DMA reception
+            w.rxdmaen().set_bit();
+            // enable DMA transmission
+            w.txdmaen().set_bit()
+        });
+
+        #[allow(unused)]
+        spi.i2spr.write(|w| unsafe {
+            w.mckoe()
+                .bit(master && PINS::MASTER_CLOCK)
+                .odd()
+                .bit(odd)
+                .i2sdiv()
+                .bits(i2sdiv)
+        });
+
+        let (datlen, chlen) = match config.data_format {
+            DataFormat::Data16Channel16 => (0b00, false),
+            DataFormat::Data16Channel32 => (0b00, true),
+            DataFormat::Data24Channel32 => (0b01, true),
+            DataFormat::Data32Channel32 => (0b10, true),
+        };
+
+        // i2smod: I2S instead of SPI
+        // i2se: enable the I2S peripheral
+        #[allow(unused)]
+        spi.i2scfgr.write(|w| unsafe {
+            w.i2smod()
+                .set_bit()
+                .i2scfg()
+                .bits(match config.mode {
+                    Mode::SlaveTransmit => 0b00,
+                    Mode::SlaveReceive => 0b01,
+                    Mode::MasterTransmit => 0b10,
+                    Mode::MasterReceive => 0b11,
+                })
+                .i2sstd()
+                .bits(match config.standard {
+                    Standard::Philips => 0b00,
+                    Standard::MsbJustified => 0b01,
+                    Standard::LsbJustified => 0b10,
+                    Standard::PcmShortFrame | Standard::PcmLongFrame => 0b11,
+                })
+                .pcmsync()
+                .bit(matches!(config.standard, Standard::PcmLongFrame))
+                .ckpol()
+                .bit(config.clock_polarity_high)
+                .datlen()
+                .bits(datlen)
+                .chlen()
+                .bit(chlen)
+                .i2se()
+                .set_bit()
+        });
+
+        Ok(I2s { spi, pins })
+    }
+
+    /// Disables the I2S peripheral and returns SPI2 and the pins
+    ///
+    /// The I2S and DMA configuration is reset, so SPI2 is back in SPI mode.
+    pub fn free(self) -> (SPI2, PINS) {
+        self.spi.i2scfgr.modify(|_, w| w.i2se().clear_bit());
+        self.spi.i2scfgr.reset();
+        self.spi.i2spr.reset();
+        self.spi.cr2.reset();
+        (self.spi, self.pins)
+    }
+
+    /// Returns true, if the next word to transmit, or the word just received,
+    /// belongs to the right channel
+    pub fn is_right_channel(&self) -> bool {
+        self.spi.sr.read().chside().bit_is_set()
+    }
+
+    /// Reads a received word
+    pub fn read(&mut self) -> nb::Result<u16, Error> {
+        let sr = self.spi.sr.read();
+
+        Err(if sr.ovr().bit_is_set() {
+            // Cleared by reading DR, then SR
+            unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u16) };
+            self.spi.sr.read();
+            nb::Error::Other(Error::Overrun)
+        } else if sr.fre().bit_is_set() {
+            nb::Error::Other(Error::FrameError)
+        } else if sr.rxne().bit_is_set() {
+            return Ok(unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u16) });
+        } else {
+            nb::Error::WouldBlock
+        })
+    }
+
+    /// Writes a word to transmit
+    pub fn write(&mut self, word: u16) -> nb::Result<(), Error> {
+        let sr = self.spi.sr.read();
+
+        Err(if sr.udr().bit_is_set() {
+            nb::Error::Other(Error::Underrun)
+        } else if sr.fre().bit_is_set() {
+            nb::Error::Other(Error::FrameError)
+        } else if sr.txe().bit_is_set() {
+            unsafe { ptr::write_volatile(&self.spi.dr as *const _ as *mut u16, word) };
+            return Ok(());
+        } else {
+            nb::Error::WouldBlock
+        })
+    }
+
+    /// Receives words into `buffer` using DMA
+    ///
+    /// With `circular`, the transfer restarts at the beginning of the buffer
+    /// when it reaches the end, and has to be stopped by the user. Use
+    /// `Transfer::transferred_half` to find out which half of the buffer can
+    /// be processed.
+    pub fn read_all<Channel, Buffer>(
+        self,
+        dma: &mut dma::Handle,
+        channel: Channel,
+        buffer: Pin<Buffer>,
+        circular: bool,
+    ) -> Transfer<Self, Rx<SPI2>, Channel, Buffer, dma::Ready>
+    where
+        Rx<SPI2>: dma::Target<Channel>,
+        Channel: dma::Channel,
+        Buffer: DerefMut + 'static,
+        Buffer::Target: AsMutSlice<Element = u16>,
+    {
+        let num_words = buffer.len();
+        let address = &unsafe { &*SPI2::ptr() }.dr as *const _ as u32;
+        // Safe, because the trait bounds of this method guarantee that the
+        // buffer can be written to.
+        let inner = unsafe {
+            dma::Transfer::new(
+                dma,
+                Rx(PhantomData),
+                channel,
+                buffer,
+                num_words,
+                address,
+                dma::Priority::high(),
+                dma::Direction::peripheral_to_memory(),
+                circular,
+            )
+        };
+        Transfer {
+            target: self,
+            inner,
+        }
+    }
+
+    /// Transmits the words in `buffer` using DMA
+    ///
+    /// See `read_all` for the meaning of `circular`.
+    pub fn write_all<Channel, Buffer>(
+        self,
+        dma: &mut dma::Handle,
+        channel: Channel,
+        buffer: Pin<Buffer>,
+        circular: bool,
+    ) -> Transfer<Self, Tx<SPI2>, Channel, Buffer, dma::Ready>
+    where
+        Tx<SPI2>: dma::Target<Channel>,
+        Channel: dma::Channel,
+        Buffer: Deref + 'static,
+        Buffer::Target: AsSlice<Element = u16>,
+    {
+        let num_words = buffer.len();
+        let address = &unsafe { &*SPI2::ptr() }.dr as *const _ as u32;
+        // Safe, because the trait bounds of this method guarantee that the
+        // buffer can be read from.
+        let inner = unsafe {
+            dma::Transfer::new(
+                dma,
+                Tx(PhantomData),
+                channel,
+                buffer,
+                num_words,
+                address,
+                dma::Priority::high(),
+                dma::Direction::memory_to_peripheral(),
+                circular,
+            )
+        };
+        Transfer {
+            target: self,
+            inner,
+        }
+    }
+}
+
+/// Half of a circular DMA buffer
+pub enum BufferHalf {
+    First,
+    Second,
+}
+
+impl<PINS, Token, Channel, Buffer> Transfer<I2s<PINS>, Token, Channel, Buffer, dma::Started>
+where
+    Channel: dma::Channel,
+{
+    /// Returns the half of a circular buffer that has just been transferred
+    ///
+    /// Clears the DMA flags, so every half is only reported once.
+    pub fn transferred_half(&self) -> Option<BufferHalf> {
+        let (_, half, complete) = self.inner.state();
+        self.inner.clear_flags();
+
+        if complete {
+            Some(BufferHalf::Second)
+        } else if half {
+            Some(BufferHalf::First)
+        } else {
+            None
+        }
+    }
+
+    /// Stops a circular transfer and returns the owned resources
+    pub fn stop(self) -> dma::TransferResources<I2s<PINS>, Channel, Buffer> {
+        let (res, _) = self.inner.stop();
+        dma::TransferResources {
+            target: self.target,
+            channel: res.channel,
+            buffer: res.buffer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prescaler;
+
+    #[test]
+    fn test_prescaler() {
+        // 16 MHz, 16 kHz, 16 bit channels: 16 MHz / (32 * 16 kHz) = 31.25
+        assert_eq!(prescaler(16_000_000, 16_000, false, false).unwrap(), (15, true));
+
+        // 32 MHz, 16 kHz, master clock: 32 MHz / (256 * 16 kHz) = 7.8
+        assert_eq!(prescaler(32_000_000, 16_000, true, false).unwrap(), (4, false));
+
+        // 32 MHz, 48 kHz, master clock: 32 MHz / (256 * 48 kHz) = 2.6, too small
+        assert!(prescaler(32_000_000, 48_000, true, false).is_err());
+    }
+}
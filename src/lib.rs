@@ -42,6 +42,8 @@ pub mod gpio;
     feature = "io-STM32L071",
 ))]
 pub mod i2c;
+#[cfg(any(feature = "stm32l0x2", feature = "stm32l0x3"))]
+pub mod i2s;
 pub mod lptim;
 pub mod mco;
 pub mod prelude;
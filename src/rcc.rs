@@ -104,6 +104,11 @@ pub enum PLLSource {
 /// HSI speed
 pub const HSI_FREQ: u32 = 16_000_000;
 
+/// LSE speed
+///
+/// The reference manual requires a 32.768 kHz crystal.
+pub const LSE_FREQ: u32 = 32_768;
+
 /// Clocks configutation
 pub struct Config {
     mux: ClockSrc,
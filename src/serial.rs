@@ -5,11 +5,12 @@ use core::ptr;
 
 use nb::block;
 
-use crate::gpio::{AltMode, PinMode};
+use crate::gpio::{AltMode, OpenDrain, Output, PinMode};
 use crate::hal;
 use crate::hal::prelude::*;
 pub use crate::pac::{LPUART1, USART1, USART2, USART4, USART5};
-use crate::rcc::{Enable, Rcc, LSE};
+use crate::rcc::{Enable, Rcc, HSI_FREQ, LSE, LSE_FREQ};
+use embedded_time::duration::Microseconds;
 use embedded_time::rate::{Baud, Extensions};
 
 #[cfg(any(feature = "stm32l0x1", feature = "stm32l0x2", feature = "stm32l0x3"))]
@@ -27,6 +28,13 @@ pub use crate::dma;
 #[cfg(any(feature = "stm32l0x1", feature = "stm32l0x2", feature = "stm32l0x3"))]
 use crate::dma::Buffer;
 
+mod buffered;
+pub mod lin;
+pub mod smartcard;
+pub mod sync;
+
+pub use self::buffered::Buffered;
+
 #[cfg(any(
     feature = "io-STM32L031",
     feature = "io-STM32L051",
@@ -49,6 +57,18 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Automatic baud rate detection failed
+    AutoBaudRate,
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::Framing | Error::Noise | Error::Parity => embedded_io::ErrorKind::InvalidData,
+            Error::Overrun | Error::AutoBaudRate => embedded_io::ErrorKind::Other,
+        }
+    }
 }
 
 /// Interrupt event
@@ -67,9 +87,36 @@ pub enum Event {
     Txe,
     /// Idle line state detected.
     Idle,
+    /// LIN break detected.
+    ///
+    /// This event is cleared by calling `clear_lin_break`. Only supported by
+    /// USART1 and USART2, in LIN mode.
+    LinBreak,
+    /// The character set with `set_character_match` has been received.
+    ///
+    /// This event is cleared by calling `Rx::clear_character_match`. When
+    /// receiving with DMA, stop the transfer using `dma::Transfer::stop` to
+    /// handle a message that ends with this character.
+    CharacterMatch,
+    /// No character has been received within the time set with
+    /// `enable_receiver_timeout`, after the last received character.
+    ///
+    /// This event is cleared by calling `Rx::clear_receiver_timeout`. When
+    /// receiving with DMA, stop the transfer using `dma::Transfer::stop` to
+    /// handle a message of unknown length. Only supported by USART1 and USART2.
+    ReceiverTimeout,
+}
+
+/// Length of the break detected in LIN mode
+pub enum LinBreakLength {
+    /// 10-bit break detection
+    Bits10,
+    /// 11-bit break detection
+    Bits11,
 }
 
 pub enum WordLength {
+    DataBits7,
     DataBits8,
     DataBits9,
 }
@@ -96,6 +143,18 @@ pub struct Config {
     pub wordlength: WordLength,
     pub parity: Parity,
     pub stopbits: StopBits,
+    /// Swap the functions of the TX and RX pins
+    pub swap: bool,
+    /// Invert the RX pin level (idle low)
+    pub rx_invert: bool,
+    /// Invert the TX pin level (idle low)
+    pub tx_invert: bool,
+    /// Invert the data bits, including parity
+    pub data_invert: bool,
+    /// Send and receive the most significant bit first
+    pub msb_first: bool,
+    /// Keep receiving when an overrun occurs, overwriting the received data
+    pub overrun_disable: bool,
 }
 
 impl Config {
@@ -119,6 +178,11 @@ impl Config {
         self
     }
 
+    pub fn wordlength_7(mut self) -> Self {
+        self.wordlength = WordLength::DataBits7;
+        self
+    }
+
     pub fn wordlength_8(mut self) -> Self {
         self.wordlength = WordLength::DataBits8;
         self
@@ -133,6 +197,36 @@ impl Config {
         self.stopbits = stopbits;
         self
     }
+
+    pub fn swap(mut self, swap: bool) -> Self {
+        self.swap = swap;
+        self
+    }
+
+    pub fn rx_invert(mut self, rx_invert: bool) -> Self {
+        self.rx_invert = rx_invert;
+        self
+    }
+
+    pub fn tx_invert(mut self, tx_invert: bool) -> Self {
+        self.tx_invert = tx_invert;
+        self
+    }
+
+    pub fn data_invert(mut self, data_invert: bool) -> Self {
+        self.data_invert = data_invert;
+        self
+    }
+
+    pub fn msb_first(mut self, msb_first: bool) -> Self {
+        self.msb_first = msb_first;
+        self
+    }
+
+    pub fn overrun_disable(mut self, overrun_disable: bool) -> Self {
+        self.overrun_disable = overrun_disable;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -146,10 +240,134 @@ impl Default for Config {
             wordlength: WordLength::DataBits8,
             parity: Parity::ParityNone,
             stopbits: StopBits::STOP1,
+            swap: false,
+            rx_invert: false,
+            tx_invert: false,
+            data_invert: false,
+            msb_first: false,
+            overrun_disable: false,
         }
     }
 }
 
+/// Polarity of the RS-485 driver enable signal
+pub enum DePolarity {
+    /// DE is high while transmitting
+    ActiveHigh,
+    /// DE is low while transmitting
+    ActiveLow,
+}
+
+/// RS-485 driver enable configuration
+///
+/// Used as an argument for `Serial::enable_rs485`.
+pub struct Rs485Config {
+    pub polarity: DePolarity,
+    /// Time between the activation of DE and the beginning of the start bit
+    pub assertion_time: Microseconds,
+    /// Time between the end of the last stop bit and the deactivation of DE
+    pub deassertion_time: Microseconds,
+}
+
+impl Rs485Config {
+    pub fn polarity(mut self, polarity: DePolarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    pub fn assertion_time(mut self, time: impl Into<Microseconds>) -> Self {
+        self.assertion_time = time.into();
+        self
+    }
+
+    pub fn deassertion_time(mut self, time: impl Into<Microseconds>) -> Self {
+        self.deassertion_time = time.into();
+        self
+    }
+}
+
+impl Default for Rs485Config {
+    fn default() -> Rs485Config {
+        Rs485Config {
+            polarity: DePolarity::ActiveHigh,
+            assertion_time: Microseconds(0),
+            deassertion_time: Microseconds(0),
+        }
+    }
+}
+
+/// Condition that makes the receiver leave mute mode
+///
+/// Used as an argument for `Serial::enable_mute_mode`.
+pub enum MuteWakeup {
+    /// Leave mute mode when an idle line is detected
+    IdleLine,
+    /// Leave mute mode when an address character matching `address` is received
+    ///
+    /// Address characters have their most significant bit set. With
+    /// `AddressLength::Bits4`, only the lower 4 bits are compared.
+    AddressMark {
+        address: u8,
+        length: AddressLength,
+    },
+}
+
+/// Length of the node address in multiprocessor communication
+pub enum AddressLength {
+    Bits4,
+    Bits7,
+}
+
+/// Character used for automatic baud rate detection
+///
+/// Used as an argument for `Serial::enable_auto_baud`.
+pub enum AutoBaudMode {
+    /// Any character starting with a 1 bit, measuring the start bit
+    StartBit,
+    /// Any character starting with 10xx, measuring from falling edge to
+    /// falling edge
+    FallingEdge,
+    /// A 0x7F character
+    Frame0x7F,
+    /// A 0x55 character
+    Frame0x55,
+}
+
+/// IrDA SIR power mode
+///
+/// Used as an argument for `Serial::enable_irda`.
+pub enum IrdaMode {
+    /// Pulses are 3/16 of a bit time
+    Normal,
+    /// Pulses have a fixed width of about 1.6 µs, derived from the peripheral
+    /// clock
+    LowPower,
+}
+
+/// Event that wakes the MCU from Stop mode
+///
+/// Used as an argument for `Serial::enable_wakeup`.
+pub enum WakeupSource {
+    /// Wake up when the received address matches the node address
+    ///
+    /// The node address is configured with `Serial::enable_mute_mode`.
+    AddressMatch,
+    /// Wake up when a start bit is detected
+    StartBit,
+    /// Wake up when a received character is ready to be read
+    Rxne,
+}
+
+/// Kernel clock that keeps a serial port running in Stop mode
+///
+/// Used as an argument for `Serial::enable_wakeup`.
+pub enum WakeupClock {
+    /// The 16 MHz internal oscillator
+    HSI16,
+    /// The low-speed external oscillator
+    LSE(LSE),
+}
+
 /// Trait to mark serial pins with transmit capability.
 pub trait TxPin<USART> {
     fn setup(&self);
@@ -160,7 +378,33 @@ pub trait RxPin<USART> {
     fn setup(&self);
 }
 
-/// Macro to implement `TxPin` / `RxPin` for a certain pin, using a certain
+/// Trait to mark serial pins that can output the RS-485 driver enable signal.
+pub trait DePin<USART> {
+    fn setup(&self);
+}
+
+/// Trait to mark serial pins with RTS (request to send) capability.
+pub trait RtsPin<USART> {
+    fn setup(&self);
+}
+
+/// Trait to mark serial pins with CTS (clear to send) capability.
+pub trait CtsPin<USART> {
+    fn setup(&self);
+}
+
+/// Trait to mark serial pins that can output the clock in synchronous and
+/// smartcard mode.
+pub trait CkPin<USART> {
+    fn setup(&self);
+}
+
+/// Trait to mark TX pins that can be used for single-wire half-duplex mode.
+///
+/// This is implemented for TX pins that are configured as open drain outputs.
+pub trait HalfDuplexPin<USART>: TxPin<USART> {}
+
+/// Macro to implement the serial pin traits for a certain pin, using a certain
 /// alternative function and for a certain serial peripheral.
 macro_rules! impl_pins {
     ($($pin:ident, $alt:ident, $instance:ty, $trait:ident;)*) => {
@@ -170,20 +414,34 @@ macro_rules! impl_pins {
                     self.set_alt_mode(AltMode::$alt);
                 }
             }
+
+            impl_half_duplex_pin!($pin, $instance, $trait);
         )*
     }
 }
 
+/// Helper for `impl_pins`, which implements `HalfDuplexPin` for open drain TX pins.
+macro_rules! impl_half_duplex_pin {
+    ($pin:ident, $instance:ty, TxPin) => {
+        impl HalfDuplexPin<$instance> for $pin<Output<OpenDrain>> {}
+    };
+    ($pin:ident, $instance:ty, $trait:ident) => {};
+}
+
 #[cfg(feature = "io-STM32L021")]
 impl_pins!(
     PA0, AF0, USART2, RxPin;
     PA0, AF6, LPUART1, RxPin;
+    PA0, AF4, USART2, CtsPin;
     PA1, AF6, LPUART1, TxPin;
+    PA1, AF4, USART2, DePin;
+    PA1, AF4, USART2, RtsPin;
     PA2, AF4, USART2, TxPin;
     PA2, AF6, LPUART1, TxPin;
     PA3, AF4, USART2, RxPin;
     PA3, AF6, LPUART1, RxPin;
     PA4, AF6, LPUART1, TxPin;
+    PA4, AF4, USART2, CkPin;
     PA9, AF4, USART2, TxPin;
     PA10, AF4, USART2, RxPin;
     PA13, AF6, LPUART1, RxPin;
@@ -199,10 +457,14 @@ impl_pins!(
 
 #[cfg(feature = "io-STM32L031")]
 impl_pins!(
+    PA0, AF4, USART2, CtsPin;
+    PA1, AF4, USART2, DePin;
+    PA1, AF4, USART2, RtsPin;
     PA2, AF4, USART2, TxPin;
     PA2, AF6, LPUART1, TxPin;
     PA3, AF4, USART2, RxPin;
     PA3, AF6, LPUART1, RxPin;
+    PA4, AF4, USART2, CkPin;
     PA9, AF4, USART2, TxPin;
     PA10, AF4, USART2, RxPin;
     PA13, AF6, LPUART1, RxPin;
@@ -218,16 +480,32 @@ impl_pins!(
 
 #[cfg(feature = "io-STM32L051")]
 impl_pins!(
+    PA0, AF4, USART2, CtsPin;
+    PA1, AF4, USART2, DePin;
+    PA1, AF4, USART2, RtsPin;
     PA2, AF4, USART2, TxPin;
     PA3, AF4, USART2, RxPin;
+    PA4, AF4, USART2, CkPin;
+    PA6, AF4, LPUART1, CtsPin;
+    PA8, AF4, USART1, CkPin;
     PA9, AF4, USART1, TxPin;
     PA10, AF4, USART1, RxPin;
+    PA11, AF4, USART1, CtsPin;
+    PA12, AF4, USART1, DePin;
+    PA12, AF4, USART1, RtsPin;
     PA14, AF4, USART2, TxPin;
     PA15, AF4, USART2, RxPin;
+    PB1, AF4, LPUART1, DePin;
+    PB1, AF4, LPUART1, RtsPin;
     PB6, AF0, USART1, TxPin;
     PB7, AF0, USART1, RxPin;
     PB10, AF4, LPUART1, TxPin;
     PB11, AF4, LPUART1, RxPin;
+    PB12, AF2, LPUART1, DePin;
+    PB12, AF2, LPUART1, RtsPin;
+    PB13, AF4, LPUART1, CtsPin;
+    PB14, AF4, LPUART1, DePin;
+    PB14, AF4, LPUART1, RtsPin;
     PC4, AF2, LPUART1, TxPin;
     PC5, AF2, LPUART1, RxPin;
     PC10, AF0, LPUART1, TxPin;
@@ -237,25 +515,47 @@ impl_pins!(
 #[cfg(feature = "io-STM32L071")]
 impl_pins!(
     PA0, AF6, USART4, TxPin;
+    PA0, AF4, USART2, CtsPin;
     PA1, AF6, USART4, RxPin;
+    PA1, AF4, USART2, DePin;
+    PA1, AF4, USART2, RtsPin;
     PA2, AF4, USART2, TxPin;
     PA2, AF6, LPUART1, TxPin;
     PA3, AF4, USART2, RxPin;
     PA3, AF6, LPUART1, RxPin;
+    PA4, AF4, USART2, CkPin;
+    PA6, AF4, LPUART1, CtsPin;
+    PA8, AF4, USART1, CkPin;
     PA9, AF4, USART1, TxPin;
     PA10, AF4, USART1, RxPin;
+    PA11, AF4, USART1, CtsPin;
+    PA12, AF4, USART1, DePin;
+    PA12, AF4, USART1, RtsPin;
     PA13, AF6, LPUART1, RxPin;
     PA14, AF4, USART2, TxPin;
     PA14, AF6, LPUART1, TxPin;
     PA15, AF4, USART2, RxPin;
+    PA15, AF6, USART4, DePin;
+    PA15, AF6, USART4, RtsPin;
+    PB1, AF4, LPUART1, DePin;
+    PB1, AF4, LPUART1, RtsPin;
     PB3, AF6, USART5, TxPin;
     PB4, AF6, USART5, RxPin;
+    PB5, AF6, USART5, DePin;
+    PB5, AF6, USART5, RtsPin;
+    PB5, AF6, USART5, CkPin;
     PB6, AF0, USART1, TxPin;
     PB7, AF0, USART1, RxPin;
+    PB7, AF6, USART4, CtsPin;
     PB10, AF4, LPUART1, TxPin;
     PB10, AF7, LPUART1, RxPin;
     PB11, AF4, LPUART1, RxPin;
     PB11, AF7, LPUART1, TxPin;
+    PB12, AF2, LPUART1, DePin;
+    PB12, AF2, LPUART1, RtsPin;
+    PB13, AF4, LPUART1, CtsPin;
+    PB14, AF4, LPUART1, DePin;
+    PB14, AF4, LPUART1, RtsPin;
     PC0, AF6, LPUART1, RxPin;
     PC1, AF6, LPUART1, TxPin;
     PC4, AF2, LPUART1, TxPin;
@@ -265,11 +565,22 @@ impl_pins!(
     PC11, AF0, LPUART1, RxPin;
     PC11, AF6, USART4, RxPin;
     PC12, AF2, USART5, TxPin;
+    PC12, AF6, USART4, CkPin;
     PD2, AF6, USART5, RxPin;
+    PD3, AF0, USART2, CtsPin;
+    PD4, AF0, USART2, DePin;
+    PD4, AF0, USART2, RtsPin;
     PD5, AF0, USART2, TxPin;
     PD6, AF0, USART2, RxPin;
+    PD7, AF0, USART2, CkPin;
     PD8, AF0, LPUART1, TxPin;
     PD9, AF0, LPUART1, RxPin;
+    PD11, AF0, LPUART1, CtsPin;
+    PD12, AF0, LPUART1, DePin;
+    PD12, AF0, LPUART1, RtsPin;
+    PE7, AF6, USART5, DePin;
+    PE7, AF6, USART5, RtsPin;
+    PE7, AF6, USART5, CkPin;
     PE8, AF6, USART4, TxPin;
     PE9, AF6, USART4, RxPin;
     PE10, AF6, USART5, TxPin;
@@ -279,10 +590,20 @@ impl_pins!(
 /// Serial abstraction
 pub struct Serial<USART> {
     usart: USART,
+    baudrate: Baud,
     rx: Rx<USART>,
     tx: Tx<USART>,
 }
 
+/// DMA resources returned by `Serial::write_read_dma`
+#[cfg(any(feature = "stm32l0x1", feature = "stm32l0x2", feature = "stm32l0x3"))]
+pub struct HalfDuplexResources<TxChannel, TxBuffer, RxChannel, RxBuffer> {
+    pub tx_channel: TxChannel,
+    pub tx_buffer: Pin<TxBuffer>,
+    pub rx_channel: RxChannel,
+    pub rx_buffer: Pin<RxBuffer>,
+}
+
 /// Serial receiver
 pub struct Rx<USART> {
     _usart: PhantomData<USART>,
@@ -295,7 +616,7 @@ pub struct Tx<USART> {
 
 macro_rules! usart {
     ($(
-        $USARTX:ident: ($usartX:ident, $pclkX:ident, $SerialExt:ident),
+        $USARTX:ident: ($usartX:ident, $SerialExt:ident),
     )+) => {
         $(
             pub trait $SerialExt<TX, RX> {
@@ -327,11 +648,45 @@ macro_rules! usart {
                     tx.setup();
                     rx.setup();
 
+                    Self::configure(usart, config, rcc)
+                }
+
+                /// Creates a single-wire half-duplex serial port
+                ///
+                /// Only the TX pin is used, which must be configured as an open
+                /// drain output. It is released by the hardware whenever nothing is
+                /// transmitted, so the line needs an external pull-up.
+                pub fn half_duplex<TX>(
+                    usart: $USARTX,
+                    tx: TX,
+                    config: Config,
+                    rcc: &mut Rcc,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    TX: HalfDuplexPin<$USARTX>,
+                {
+                    tx.setup();
+
+                    let serial = Self::configure(usart, config, rcc)?;
+
+                    // HDSEL can only be written while the peripheral is disabled
+                    serial.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    serial.usart.cr3.modify(|_, w| w.hdsel().set_bit());
+                    serial.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    Ok(serial)
+                }
+
+                fn configure(
+                    usart: $USARTX,
+                    config: Config,
+                    rcc: &mut Rcc,
+                ) -> Result<Self, InvalidConfig> {
                     // Enable clock for USART
                     <$USARTX>::enable(rcc);
 
                     // Calculate correct baudrate divisor on the fly
-                    let div = (rcc.clocks.$pclkX().0 * 25) / (4 * config.baudrate.0);
+                    let div = (Self::kernel_clk(rcc) * 25) / (4 * config.baudrate.0);
                     let mantissa = div / 100;
                     let fraction = ((div - mantissa * 100) * 16 + 50) / 100;
                     let mut brr = mantissa << 4 | fraction;
@@ -344,8 +699,28 @@ macro_rules! usart {
                         .brr
                         .write(|w| unsafe { w.bits(brr) });
 
-                    // Reset other registers to disable advanced USART features
-                    usart.cr2.reset();
+                    // Configure stop bits and line options, and disable other
+                    // advanced USART features. This must be done before the
+                    // peripheral is enabled.
+                    usart.cr2.write(|w| unsafe {
+                        w.stop()
+                            .bits(match config.stopbits {
+                                StopBits::STOP1 => 0b00,
+                                StopBits::STOP0P5 => 0b01,
+                                StopBits::STOP2 => 0b10,
+                                StopBits::STOP1P5 => 0b11,
+                            })
+                            .swap()
+                            .bit(config.swap)
+                            .rxinv()
+                            .bit(config.rx_invert)
+                            .txinv()
+                            .bit(config.tx_invert)
+                            .datainv()
+                            .bit(config.data_invert)
+                            .msbfirst()
+                            .bit(config.msb_first)
+                    });
 
                     // Enable DMA
                     usart.cr3.write(|w|
@@ -355,6 +730,7 @@ macro_rules! usart {
                             // Enable DMA
                             .dmat().enabled()
                             .dmar().enabled()
+                            .ovrdis().bit(config.overrun_disable)
                     );
 
                     // Enable transmission and receiving
@@ -366,10 +742,14 @@ macro_rules! usart {
                             .set_bit()
                             .re()
                             .set_bit()
-                            .m0()
+                            .m1()
+                            .bit(match config.wordlength {
+                                WordLength::DataBits7 => true,
+                                _ => false,
+                            }).m0()
                             .bit(match config.wordlength {
-                                WordLength::DataBits8 => false,
                                 WordLength::DataBits9 => true,
+                                _ => false,
                             }).pce()
                             .bit(match config.parity {
                                 Parity::ParityNone => false,
@@ -381,16 +761,9 @@ macro_rules! usart {
                             })
                     });
 
-                    usart.cr2.write(|w|
-                        w.stop().bits(match config.stopbits {
-                            StopBits::STOP1 => 0b00,
-                            StopBits::STOP0P5 => 0b01,
-                            StopBits::STOP2 => 0b10,
-                            StopBits::STOP1P5 => 0b11,
-                        })
-                    );
                     Ok(Serial {
                         usart,
+                        baudrate: config.baudrate,
                         tx: Tx { _usart: PhantomData },
                         rx: Rx { _usart: PhantomData },
                     })
@@ -408,6 +781,11 @@ macro_rules! usart {
                         Event::Idle => {
                             self.usart.cr1.modify(|_, w| w.idleie().set_bit())
                         },
+                        Event::LinBreak => self.set_lin_break_interrupt(true),
+                        Event::CharacterMatch => {
+                            self.usart.cr1.modify(|_, w| w.cmie().set_bit())
+                        },
+                        Event::ReceiverTimeout => self.set_receiver_timeout_interrupt(true),
                     }
                 }
 
@@ -423,6 +801,11 @@ macro_rules! usart {
                         Event::Idle => {
                             self.usart.cr1.modify(|_, w| w.idleie().clear_bit())
                         },
+                        Event::LinBreak => self.set_lin_break_interrupt(false),
+                        Event::CharacterMatch => {
+                            self.usart.cr1.modify(|_, w| w.cmie().clear_bit())
+                        },
+                        Event::ReceiverTimeout => self.set_receiver_timeout_interrupt(false),
                     }
                 }
 
@@ -449,6 +832,12 @@ macro_rules! usart {
                         Some(Event::Txe)
                     } else if cr1.idleie().bit_is_set() && isr.idle().bit_is_set() {
                         Some(Event::Idle)
+                    } else if self.is_lin_break_pending() {
+                        Some(Event::LinBreak)
+                    } else if cr1.cmie().bit_is_set() && isr.cmf().bit_is_set() {
+                        Some(Event::CharacterMatch)
+                    } else if self.is_receiver_timeout_pending() {
+                        Some(Event::ReceiverTimeout)
                     } else {
                         None
                     }
@@ -467,6 +856,186 @@ macro_rules! usart {
                     self.rx.clear_errors()
                 }
 
+                /// Sends `bytes`, then receives `buffer.len()` bytes
+                ///
+                /// This is meant to be used with a serial port created by
+                /// `half_duplex`. The receiver is disabled while sending, so the
+                /// transmitted bytes are not echoed back into `buffer`.
+                ///
+                /// This method blocks until all bytes have been received.
+                pub fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+                    self.set_receiver(false);
+                    for &byte in bytes {
+                        block!(self.tx.write(byte))?;
+                    }
+                    block!(self.tx.flush())?;
+                    self.set_receiver(true);
+
+                    for byte in buffer {
+                        *byte = block!(self.rx.read())?;
+                    }
+
+                    Ok(())
+                }
+
+                /// Sets the character that triggers `Event::CharacterMatch`
+                ///
+                /// The match is done on all 8 bits of the received character. This
+                /// shares its register with the node address of
+                /// `enable_mute_mode`, so both can't be used at the same time.
+                pub fn set_character_match(&mut self, character: u8) {
+                    // ADD can only be written while the peripheral is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.addm7().set_bit().add().bits(character)
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Enables multiprocessor mute mode
+                ///
+                /// After calling `enter_mute_mode`, the receiver ignores all
+                /// characters until the `wakeup` condition occurs. Returns
+                /// `InvalidConfig`, if the address doesn't fit into the address
+                /// length.
+                pub fn enable_mute_mode(&mut self, wakeup: MuteWakeup) -> Result<(), InvalidConfig> {
+                    let (address_mark, address, addm7) = match wakeup {
+                        MuteWakeup::IdleLine => (false, 0, false),
+                        MuteWakeup::AddressMark { address, length: AddressLength::Bits4 } => {
+                            if address > 0x0f {
+                                return Err(InvalidConfig);
+                            }
+                            (true, address, false)
+                        }
+                        MuteWakeup::AddressMark { address, length: AddressLength::Bits7 } => {
+                            if address > 0x7f {
+                                return Err(InvalidConfig);
+                            }
+                            (true, address, true)
+                        }
+                    };
+
+                    // WAKE, ADDM7 and ADD can only be written while the peripheral
+                    // is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.addm7().bit(addm7).add().bits(address)
+                    });
+                    self.usart.cr1.modify(|_, w| {
+                        w.wake()
+                            .bit(address_mark)
+                            .mme()
+                            .set_bit()
+                            .ue()
+                            .set_bit()
+                    });
+
+                    Ok(())
+                }
+
+                /// Disables multiprocessor mute mode
+                pub fn disable_mute_mode(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.mme().clear_bit());
+                }
+
+                /// Mutes the receiver until the configured wakeup condition occurs
+                pub fn enter_mute_mode(&mut self) {
+                    self.usart.rqr.write(|w| w.mmrq().set_bit());
+                }
+
+                /// Returns true if the receiver is in mute mode
+                pub fn is_muted(&self) -> bool {
+                    self.usart.isr.read().rwu().bit_is_set()
+                }
+
+                /// Enables or disables the receiver, discarding any stale data
+                fn set_receiver(&mut self, enabled: bool) {
+                    if enabled {
+                        self.rx.clear_errors();
+                        self.usart.rqr.write(|w| w.rxfrq().set_bit());
+                    }
+                    self.usart.cr1.modify(|_, w| w.re().bit(enabled));
+                }
+
+                /// Enables RS-485 driver enable mode
+                ///
+                /// The `de` pin is driven by hardware while a character is being
+                /// transmitted, including the configured assertion and deassertion
+                /// times. Returns `InvalidConfig`, if one of those times is too long
+                /// for the configured baud rate.
+                pub fn enable_rs485<DE>(&mut self, de: DE, config: Rs485Config) -> Result<(), InvalidConfig>
+                where
+                    DE: DePin<$USARTX>,
+                {
+                    // DEAT/DEDT are expressed in sample times (1/16 bit time) for
+                    // USARTs, and in kernel clock cycles for LPUART1.
+                    let units_per_s = if stringify!($usartX) == "lpuart1" {
+                        u64::from(self.usart.brr.read().bits()) * u64::from(self.baudrate.0) / 256
+                    } else {
+                        u64::from(self.baudrate.0) * 16
+                    };
+                    let to_units = |time: Microseconds| {
+                        (u64::from(time.0) * units_per_s + 999_999) / 1_000_000
+                    };
+                    let deat = to_units(config.assertion_time);
+                    let dedt = to_units(config.deassertion_time);
+                    if deat > 0b11111 || dedt > 0b11111 {
+                        return Err(InvalidConfig);
+                    }
+
+                    de.setup();
+
+                    // DEM, DEP, DEAT and DEDT can only be written while the
+                    // peripheral is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr3.modify(|_, w| {
+                        w.dem()
+                            .set_bit()
+                            .dep()
+                            .bit(match config.polarity {
+                                DePolarity::ActiveHigh => false,
+                                DePolarity::ActiveLow => true,
+                            })
+                    });
+                    self.usart.cr1.modify(|_, w| unsafe {
+                        w.deat()
+                            .bits(deat as u8)
+                            .dedt()
+                            .bits(dedt as u8)
+                            .ue()
+                            .set_bit()
+                    });
+
+                    Ok(())
+                }
+
+                /// Enables hardware flow control
+                ///
+                /// The `rts` pin is asserted (driven low) by hardware only while
+                /// there is room in the receive buffer. Transmission of the next
+                /// character only starts while the `cts` pin is asserted (low).
+                /// Flow control is only enabled for the pins that are provided.
+                pub fn enable_flow_control<RTS, CTS>(&mut self, rts: Option<RTS>, cts: Option<CTS>)
+                where
+                    RTS: RtsPin<$USARTX>,
+                    CTS: CtsPin<$USARTX>,
+                {
+                    if let Some(rts) = &rts {
+                        rts.setup();
+                    }
+                    if let Some(cts) = &cts {
+                        cts.setup();
+                    }
+
+                    // RTSE and CTSE can only be written while the peripheral is
+                    // disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart
+                        .cr3
+                        .modify(|_, w| w.rtse().bit(rts.is_some()).ctse().bit(cts.is_some()));
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
                 pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
                     (self.tx, self.rx)
                 }
@@ -519,6 +1088,21 @@ macro_rules! usart {
                     icr.write(|w| w.idlecf().set_bit());
                 }
 
+                /// Returns true if the character set with `set_character_match`
+                /// has been received
+                ///
+                /// This flag is set by hardware even when the interrupt is disabled.
+                pub fn is_character_match(&self) -> bool {
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+                    isr.cmf().bit_is_set()
+                }
+
+                /// Clear character match flag
+                pub fn clear_character_match(&self) {
+                    let icr = unsafe { &(*$USARTX::ptr()).icr };
+                    icr.write(|w| w.cmcf().set_bit());
+                }
+
                 /// Checks for reception errors that may have occurred.
                 ///
                 /// Note that multiple errors can be signaled at the same time. In that case,
@@ -546,6 +1130,16 @@ macro_rules! usart {
                     Ok(())
                 }
 
+                /// Returns true if any reception error is signaled, without clearing it.
+                #[cfg(feature = "embedded-io")]
+                fn has_errors(&self) -> bool {
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+                    isr.pe().bit_is_set()
+                        || isr.fe().bit_is_set()
+                        || isr.nf().bit_is_set()
+                        || isr.ore().bit_is_set()
+                }
+
                 /// Clears any signaled errors without returning them.
                 pub fn clear_errors(&mut self) {
                     let icr = unsafe { &(*$USARTX::ptr()).icr };
@@ -679,6 +1273,180 @@ macro_rules! usart {
                 }
             }
 
+            /// DMA operations.
+            #[cfg(any(feature = "stm32l0x1", feature = "stm32l0x2", feature = "stm32l0x3"))]
+            impl Serial<$USARTX> {
+                /// Sends `tx_buffer`, then receives into `rx_buffer`, using DMA
+                ///
+                /// This is the DMA equivalent of `write_read`, and is meant to be
+                /// used with a serial port created by `half_duplex`. This method
+                /// waits until both transfers have finished and returns the DMA
+                /// resources, together with the result of the transaction.
+                pub fn write_read_dma<TxChannel, TxBuffer, RxChannel, RxBuffer>(&mut self,
+                    dma:        &mut dma::Handle,
+                    tx_channel: TxChannel,
+                    tx_buffer:  Pin<TxBuffer>,
+                    rx_channel: RxChannel,
+                    rx_buffer:  Pin<RxBuffer>,
+                )
+                    -> (
+                        HalfDuplexResources<TxChannel, TxBuffer, RxChannel, RxBuffer>,
+                        Result<(), dma::Error>,
+                    )
+                    where
+                        Tx<$USARTX>:      dma::Target<TxChannel>,
+                        Rx<$USARTX>:      dma::Target<RxChannel>,
+                        TxChannel:        dma::Channel,
+                        RxChannel:        dma::Channel,
+                        TxBuffer:         Deref + 'static,
+                        TxBuffer::Target: AsSlice<Element=u8>,
+                        RxBuffer:         DerefMut + 'static,
+                        RxBuffer::Target: AsMutSlice<Element=u8>,
+                {
+                    // We have exclusive access to the serial port for the whole
+                    // transaction, so it's fine to create additional tokens here.
+                    let tx = Tx { _usart: PhantomData };
+                    let rx = Rx { _usart: PhantomData };
+
+                    self.set_receiver(false);
+                    let (tx_channel, tx_buffer, tx_result) =
+                        match tx.write_all(dma, tx_buffer, tx_channel).start().wait() {
+                            Ok(res) => (res.channel, res.buffer, Ok(())),
+                            Err((res, err)) => (res.channel, res.buffer, Err(err)),
+                        };
+                    // Wait for the last character to leave the shift register
+                    let _ = block!(self.tx.flush());
+                    self.set_receiver(true);
+
+                    if let Err(err) = tx_result {
+                        return (
+                            HalfDuplexResources { tx_channel, tx_buffer, rx_channel, rx_buffer },
+                            Err(err),
+                        );
+                    }
+
+                    let (rx_channel, rx_buffer, rx_result) =
+                        match rx.read_all(dma, rx_buffer, rx_channel).start().wait() {
+                            Ok(res) => (res.channel, res.buffer, Ok(())),
+                            Err((res, err)) => (res.channel, res.buffer, Err(err)),
+                        };
+
+                    (
+                        HalfDuplexResources { tx_channel, tx_buffer, rx_channel, rx_buffer },
+                        rx_result,
+                    )
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::ErrorType for Serial<$USARTX> {
+                type Error = Error;
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::Read for Serial<$USARTX> {
+                fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                    embedded_io::Read::read(&mut self.rx, buf)
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::ReadReady for Serial<$USARTX> {
+                fn read_ready(&mut self) -> Result<bool, Error> {
+                    embedded_io::ReadReady::read_ready(&mut self.rx)
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::Write for Serial<$USARTX> {
+                fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                    embedded_io::Write::write(&mut self.tx, buf)
+                }
+
+                fn flush(&mut self) -> Result<(), Error> {
+                    embedded_io::Write::flush(&mut self.tx)
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::WriteReady for Serial<$USARTX> {
+                fn write_ready(&mut self) -> Result<bool, Error> {
+                    embedded_io::WriteReady::write_ready(&mut self.tx)
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::ErrorType for Rx<$USARTX> {
+                type Error = Error;
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::Read for Rx<$USARTX> {
+                /// Blocks until at least one byte has been received, then returns
+                /// all bytes that can be read without blocking.
+                fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    buf[0] = block!(hal::serial::Read::read(self))?;
+                    let mut n = 1;
+
+                    // Stop early if an error is flagged. It will be returned by the
+                    // next call, so the bytes read so far don't get lost.
+                    while n < buf.len() && self.is_rx_not_empty() && !self.has_errors() {
+                        buf[n] = block!(hal::serial::Read::read(self))?;
+                        n += 1;
+                    }
+
+                    Ok(n)
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::ReadReady for Rx<$USARTX> {
+                fn read_ready(&mut self) -> Result<bool, Error> {
+                    Ok(self.is_rx_not_empty())
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::ErrorType for Tx<$USARTX> {
+                type Error = Error;
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::Write for Tx<$USARTX> {
+                /// Blocks until at least one byte has been written, then writes as
+                /// many bytes as can be written without blocking.
+                fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    block!(hal::serial::Write::write(self, buf[0]))?;
+                    let mut n = 1;
+
+                    while n < buf.len() && self.is_tx_empty() {
+                        block!(hal::serial::Write::write(self, buf[n]))?;
+                        n += 1;
+                    }
+
+                    Ok(n)
+                }
+
+                fn flush(&mut self) -> Result<(), Error> {
+                    block!(hal::serial::Write::flush(self))
+                }
+            }
+
+            #[cfg(feature = "embedded-io")]
+            impl embedded_io::WriteReady for Tx<$USARTX> {
+                fn write_ready(&mut self) -> Result<bool, Error> {
+                    Ok(self.is_tx_empty())
+                }
+            }
+
             #[cfg(any(feature = "stm32l0x1", feature = "stm32l0x2", feature = "stm32l0x3"))]
             impl Tx<$USARTX> {
                 pub fn write_all<Buffer, Channel>(self,
@@ -744,55 +1512,710 @@ macro_rules! usart {
     feature = "io-STM32L071",
 ))]
 usart! {
-    LPUART1: (lpuart1, apb1_clk, Serial1LpExt),
-    USART2: (usart2, apb1_clk, Serial2Ext),
+    LPUART1: (lpuart1, Serial1LpExt),
+    USART2: (usart2, Serial2Ext),
 }
 
 // USART1 is available on category 3/5 MCUs
 #[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
 usart! {
-    USART1: (usart1, apb1_clk, Serial1Ext),
+    USART1: (usart1, Serial1Ext),
 }
 
 // USART4 and USART5 are available on category 5 MCUs
 #[cfg(feature = "io-STM32L071")]
 usart! {
-    USART4: (usart4, apb1_clk, Serial4Ext),
-    USART5: (usart5, apb1_clk, Serial5Ext),
+    USART4: (usart4, Serial4Ext),
+    USART5: (usart5, Serial5Ext),
+}
+
+macro_rules! usart_kernel_clock {
+    // The kernel clock is selected in RCC_CCIPR
+    ($($USARTX:ident: ($pclkX:ident, $usartXsel:ident),)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Returns the frequency of the kernel clock
+                fn kernel_clk(rcc: &Rcc) -> u32 {
+                    match rcc.rb.ccipr.read().$usartXsel().bits() {
+                        0b00 => rcc.clocks.$pclkX().0,
+                        0b01 => rcc.clocks.sys_clk().0,
+                        0b10 => HSI_FREQ,
+                        _ => LSE_FREQ,
+                    }
+                }
+            }
+        )+
+    };
+    // The kernel clock is always the peripheral clock
+    ($($USARTX:ident: $pclkX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Returns the frequency of the kernel clock
+                fn kernel_clk(rcc: &Rcc) -> u32 {
+                    rcc.clocks.$pclkX().0
+                }
+            }
+        )+
+    };
+}
+
+usart_kernel_clock! {
+    LPUART1: (apb1_clk, lpuart1sel),
+    USART2: (apb1_clk, usart2sel),
+}
+
+// USART1 is the only one on APB2
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+usart_kernel_clock! {
+    USART1: (apb2_clk, usart1sel),
+}
+
+#[cfg(feature = "io-STM32L071")]
+usart_kernel_clock! {
+    USART4: apb1_clk,
+    USART5: apb1_clk,
+}
+
+macro_rules! usart_lin {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Enables LIN mode
+                ///
+                /// LIN mode requires 1 stop bit, which is configured by this
+                /// method. The receiver timeout is enabled too, so the `lin_*`
+                /// methods return `lin::Error::Timeout` instead of waiting forever
+                /// for a missing byte. It can be changed with
+                /// `enable_receiver_timeout`.
+                pub fn enable_lin(&mut self, break_length: LinBreakLength) {
+                    // LINEN and LBDL can only be written while the peripheral is
+                    // disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr3.modify(|_, w| {
+                        w.scen()
+                            .clear_bit()
+                            .hdsel()
+                            .clear_bit()
+                            .iren()
+                            .clear_bit()
+                    });
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.stop()
+                            .bits(0b00)
+                            .clken()
+                            .clear_bit()
+                            .lbdl()
+                            .bit(match break_length {
+                                LinBreakLength::Bits10 => false,
+                                LinBreakLength::Bits11 => true,
+                            })
+                            .linen()
+                            .set_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    self.enable_receiver_timeout(lin::TIMEOUT_BITS).ok();
+                }
+
+                /// Disables LIN mode
+                pub fn disable_lin(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| w.linen().clear_bit());
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Requests the transmission of a break
+                ///
+                /// The break is sent after the current character.
+                pub fn send_break(&mut self) {
+                    self.usart.rqr.write(|w| w.sbkrq().set_bit());
+                }
+
+                /// Returns true if a LIN break has been detected
+                pub fn is_lin_break(&self) -> bool {
+                    self.usart.isr.read().lbdf().bit_is_set()
+                }
+
+                /// Clears the LIN break detection flag
+                pub fn clear_lin_break(&mut self) {
+                    self.usart.icr.write(|w| w.lbdcf().set_bit());
+                }
+
+                /// Sends a LIN header
+                ///
+                /// The header consists of a break, the sync byte and the protected
+                /// identifier of `id`. All bytes sent on a LIN bus are read back by
+                /// the transceiver, so this method blocks until the header has been
+                /// sent, and returns `lin::Error::Readback`, if it wasn't read back
+                /// unchanged.
+                pub fn lin_write_header(&mut self, id: u8) -> Result<(), lin::Error> {
+                    block!(self.tx.flush())?;
+                    self.send_break();
+
+                    // The break is detected by the receiver before its
+                    // transmission ends
+                    while self.usart.isr.read().sbkf().bit_is_set() {}
+                    if !self.lin_take_break() {
+                        return Err(lin::Error::Readback);
+                    }
+
+                    self.lin_write_byte(lin::SYNC)?;
+                    self.lin_write_byte(lin::protected_id(id))
+                }
+
+                /// Receives a LIN header and returns its frame identifier
+                ///
+                /// Returns `WouldBlock`, until a break has been detected. Bytes
+                /// received before the break are discarded. After the break, this
+                /// method blocks until the sync byte and protected identifier have
+                /// been received, or the receiver timeout elapses.
+                pub fn lin_read_header(&mut self) -> nb::Result<u8, lin::Error> {
+                    if !self.lin_take_break() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    if self.lin_read_byte()? != lin::SYNC {
+                        return Err(nb::Error::Other(lin::Error::Sync));
+                    }
+                    let pid = self.lin_read_byte()?;
+
+                    Ok(lin::frame_id(pid)?)
+                }
+
+                /// Sends the response of the frame `id`, followed by its checksum
+                ///
+                /// As with `lin_write_header`, every byte is read back and verified.
+                pub fn lin_write_response(
+                    &mut self,
+                    id: u8,
+                    data: &[u8],
+                    model: lin::Checksum,
+                ) -> Result<(), lin::Error> {
+                    for &byte in data {
+                        self.lin_write_byte(byte)?;
+                    }
+                    self.lin_write_byte(lin::checksum(lin::protected_id(id), data, model))
+                }
+
+                /// Receives the response of the frame `id` and verifies its checksum
+                ///
+                /// Returns `lin::Error::Timeout`, if the receiver timeout elapses
+                /// before the response is complete, e.g. because no node responds.
+                pub fn lin_read_response(
+                    &mut self,
+                    id: u8,
+                    buffer: &mut [u8],
+                    model: lin::Checksum,
+                ) -> Result<(), lin::Error> {
+                    for byte in buffer.iter_mut() {
+                        *byte = self.lin_read_byte()?;
+                    }
+
+                    let checksum = self.lin_read_byte()?;
+                    if checksum != lin::checksum(lin::protected_id(id), buffer, model) {
+                        return Err(lin::Error::Checksum);
+                    }
+
+                    Ok(())
+                }
+
+                /// Discards the character produced by a detected break
+                ///
+                /// Returns false, if no break has been detected.
+                fn lin_take_break(&mut self) -> bool {
+                    if !self.is_lin_break() {
+                        return false;
+                    }
+                    self.clear_lin_break();
+
+                    // A break is also received as a 0x00 character with a
+                    // framing error
+                    self.rx.clear_errors();
+                    self.usart.rqr.write(|w| w.rxfrq().set_bit());
+
+                    // Every frame starts with a break, so this is where the
+                    // receiver timeout of the previous frame is discarded
+                    self.rx.clear_receiver_timeout();
+
+                    true
+                }
+
+                /// Receives a byte of the current frame
+                fn lin_read_byte(&mut self) -> Result<u8, lin::Error> {
+                    loop {
+                        match self.rx.read() {
+                            Ok(byte) => return Ok(byte),
+                            Err(nb::Error::Other(err)) => return Err(err.into()),
+                            Err(nb::Error::WouldBlock) => {
+                                if self.rx.is_receiver_timeout() {
+                                    return Err(lin::Error::Timeout);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                /// Sends a byte, and verifies that it has been read back
+                fn lin_write_byte(&mut self, byte: u8) -> Result<(), lin::Error> {
+                    block!(self.tx.write(byte))?;
+                    block!(self.tx.flush())?;
+
+                    // The byte has been received before its transmission
+                    // completed, unless no transceiver is connected
+                    match self.rx.read() {
+                        Ok(read) if read == byte => Ok(()),
+                        Ok(_) | Err(nb::Error::WouldBlock) => Err(lin::Error::Readback),
+                        Err(nb::Error::Other(err)) => Err(err.into()),
+                    }
+                }
+
+                fn set_lin_break_interrupt(&mut self, enable: bool) {
+                    self.usart.cr2.modify(|_, w| w.lbdie().bit(enable));
+                }
+
+                fn is_lin_break_pending(&self) -> bool {
+                    self.usart.cr2.read().lbdie().bit_is_set() && self.is_lin_break()
+                }
+            }
+        )+
+    }
+}
+
+// LIN mode is not supported by these instances
+macro_rules! usart_no_lin {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                fn set_lin_break_interrupt(&mut self, _: bool) {}
+
+                fn is_lin_break_pending(&self) -> bool {
+                    false
+                }
+            }
+        )+
+    }
+}
+
+usart_lin! {
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+usart_lin! {
+    USART1,
+}
+
+usart_no_lin! {
+    LPUART1,
+}
+
+#[cfg(feature = "io-STM32L071")]
+usart_no_lin! {
+    USART4,
+    USART5,
+}
+
+macro_rules! usart_rto {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Enables the receiver timeout
+                ///
+                /// `Event::ReceiverTimeout` is signaled if the line stays idle for
+                /// `bits` bit times after the last received character. Returns
+                /// `InvalidConfig`, if `bits` doesn't fit into 24 bits.
+                pub fn enable_receiver_timeout(&mut self, bits: u32) -> Result<(), InvalidConfig> {
+                    if bits > 0xff_ffff {
+                        return Err(InvalidConfig);
+                    }
+
+                    self.usart.rtor.modify(|_, w| unsafe { w.rto().bits(bits) });
+                    self.usart.cr2.modify(|_, w| w.rtoen().set_bit());
+
+                    Ok(())
+                }
+
+                /// Disables the receiver timeout
+                pub fn disable_receiver_timeout(&mut self) {
+                    self.usart.cr2.modify(|_, w| w.rtoen().clear_bit());
+                }
+
+                fn set_receiver_timeout_interrupt(&mut self, enable: bool) {
+                    self.usart.cr1.modify(|_, w| w.rtoie().bit(enable));
+                }
+
+                fn is_receiver_timeout_pending(&self) -> bool {
+                    self.usart.cr1.read().rtoie().bit_is_set()
+                        && self.usart.isr.read().rtof().bit_is_set()
+                }
+            }
+
+            impl Rx<$USARTX> {
+                /// Returns true if the receiver timeout has elapsed
+                ///
+                /// This flag is set by hardware even when the interrupt is disabled.
+                pub fn is_receiver_timeout(&self) -> bool {
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+                    isr.rtof().bit_is_set()
+                }
+
+                /// Clear receiver timeout flag
+                pub fn clear_receiver_timeout(&self) {
+                    let icr = unsafe { &(*$USARTX::ptr()).icr };
+                    icr.write(|w| w.rtocf().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+// The receiver timeout is not supported by these instances
+macro_rules! usart_no_rto {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                fn set_receiver_timeout_interrupt(&mut self, _: bool) {}
+
+                fn is_receiver_timeout_pending(&self) -> bool {
+                    false
+                }
+            }
+        )+
+    }
+}
+
+usart_rto! {
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+usart_rto! {
+    USART1,
+}
+
+usart_no_rto! {
+    LPUART1,
+}
+
+#[cfg(feature = "io-STM32L071")]
+usart_no_rto! {
+    USART4,
+    USART5,
+}
+
+macro_rules! usart_auto_baud {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Enables automatic baud rate detection
+                ///
+                /// The baud rate is measured on the next received character that
+                /// matches `mode`. Use `auto_baud_rate` to check for the result.
+                pub fn enable_auto_baud(&mut self, mode: AutoBaudMode) {
+                    // ABREN and ABRMOD can only be written while the peripheral is
+                    // disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.abrmod()
+                            .bits(match mode {
+                                AutoBaudMode::StartBit => 0b00,
+                                AutoBaudMode::FallingEdge => 0b01,
+                                AutoBaudMode::Frame0x7F => 0b10,
+                                AutoBaudMode::Frame0x55 => 0b11,
+                            })
+                            .abren()
+                            .set_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Disables automatic baud rate detection
+                pub fn disable_auto_baud(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.cr2.modify(|_, w| w.abren().clear_bit());
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Measures the baud rate again on the next matching character
+                pub fn rearm_auto_baud(&mut self) {
+                    self.usart.rqr.write(|w| w.abrrq().set_bit());
+                }
+
+                /// Returns the detected baud rate
+                ///
+                /// Returns `WouldBlock`, while no matching character has been
+                /// received yet, and `Error::AutoBaudRate`, if the measured baud
+                /// rate is out of range. In that case, detection is re-armed.
+                ///
+                /// The character used for detection is received normally, and
+                /// should be read from the receiver.
+                pub fn auto_baud_rate(&mut self, rcc: &Rcc) -> nb::Result<Baud, Error> {
+                    let isr = self.usart.isr.read();
+
+                    if isr.abre().bit_is_set() {
+                        self.rearm_auto_baud();
+                        return Err(nb::Error::Other(Error::AutoBaudRate));
+                    }
+                    if isr.abrf().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    // The hardware writes the measured divider to BRR
+                    let brr = self.usart.brr.read().bits();
+                    if brr == 0 {
+                        return Err(nb::Error::Other(Error::AutoBaudRate));
+                    }
+                    self.baudrate = Baud(Self::kernel_clk(rcc) / brr);
+
+                    Ok(self.baudrate)
+                }
+            }
+        )+
+    }
+}
+
+usart_auto_baud! {
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+usart_auto_baud! {
+    USART1,
+}
+
+macro_rules! usart_irda {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Enables the IrDA SIR encoder and decoder
+                ///
+                /// The TX and RX pins are connected to the infrared transceiver.
+                /// Returns `InvalidConfig`, if the configured baud rate exceeds the
+                /// 115200 Bd allowed by IrDA SIR, or if the low-power pulse
+                /// frequency can't be derived from the kernel clock.
+                pub fn enable_irda(&mut self, mode: IrdaMode, rcc: &Rcc) -> Result<(), InvalidConfig> {
+                    if self.baudrate.0 > 115_200 {
+                        return Err(InvalidConfig);
+                    }
+
+                    let psc = match mode {
+                        // The prescaler must be 1 in normal mode
+                        IrdaMode::Normal => 1,
+                        // Divide the kernel clock down to the low-power
+                        // frequency, which must be between 1.42 MHz and 2.12 MHz
+                        IrdaMode::LowPower => {
+                            let kernel_clk = Self::kernel_clk(rcc);
+                            let psc = (kernel_clk + 1_843_200 / 2) / 1_843_200;
+                            if psc == 0 || psc > 0xff {
+                                return Err(InvalidConfig);
+                            }
+                            let freq = kernel_clk / psc;
+                            if !(1_420_000..=2_120_000).contains(&freq) {
+                                return Err(InvalidConfig);
+                            }
+                            psc as u8
+                        }
+                    };
+
+                    // IREN, IRLP and PSC can only be written while the peripheral
+                    // is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.gtpr.modify(|_, w| unsafe { w.psc().bits(psc) });
+                    self.usart.cr2.modify(|_, w| unsafe {
+                        w.linen()
+                            .clear_bit()
+                            .stop()
+                            .bits(0b00)
+                            .clken()
+                            .clear_bit()
+                    });
+                    self.usart.cr3.modify(|_, w| {
+                        w.scen()
+                            .clear_bit()
+                            .hdsel()
+                            .clear_bit()
+                            .irlp()
+                            .bit(matches!(mode, IrdaMode::LowPower))
+                            .iren()
+                            .set_bit()
+                    });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    Ok(())
+                }
+
+                /// Disables the IrDA SIR encoder and decoder
+                pub fn disable_irda(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart
+                        .cr3
+                        .modify(|_, w| w.iren().clear_bit().irlp().clear_bit());
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+usart_irda! {
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+usart_irda! {
+    USART1,
+}
+
+macro_rules! usart_wakeup {
+    ($(
+        $USARTX:ident: ($usartXsel:ident, $brr_mul:expr, $brr_min:expr, $brr_max:expr),
+    )+) => {
+        $(
+            impl Serial<$USARTX> {
+                /// Enables waking up the MCU from Stop mode
+                ///
+                /// Switches the kernel clock of the serial port to `clock`, keeps
+                /// that clock running in Stop mode and enables the wakeup
+                /// interrupt for `source`. The baud rate is recalculated for the
+                /// new kernel clock.
+                ///
+                /// To actually wake up, the matching `exti::DirectLine` must be
+                /// enabled, e.g. by using `Exti::wait_for_irq` together with
+                /// `PWR::stop_mode`. Make sure no transfer is ongoing before
+                /// entering Stop mode.
+                ///
+                /// Returns `InvalidConfig`, if the configured baud rate can't be
+                /// derived from the selected clock.
+                pub fn enable_wakeup(
+                    &mut self,
+                    source: WakeupSource,
+                    clock: WakeupClock,
+                    rcc: &mut Rcc,
+                ) -> Result<(), InvalidConfig> {
+                    self.set_kernel_clock(clock, rcc)?;
+
+                    self.usart.cr3.modify(|_, w| unsafe {
+                        w.wus()
+                            .bits(match source {
+                                WakeupSource::AddressMatch => 0b00,
+                                WakeupSource::StartBit => 0b10,
+                                WakeupSource::Rxne => 0b11,
+                            })
+                            // Keep the kernel clock enabled in Stop mode
+                            .ucesm()
+                            .set_bit()
+                            .wufie()
+                            .set_bit()
+                    });
+
+                    self.usart
+                        .cr1
+                        .modify(|_, w| w.ue().set_bit().uesm().set_bit());
+
+                    Ok(())
+                }
+
+                /// Switches the kernel clock to `clock`, and recalculates the baud
+                /// rate for it
+                ///
+                /// The peripheral is left disabled, if the configured baud rate
+                /// can be derived from `clock`.
+                fn set_kernel_clock(
+                    &mut self,
+                    clock: WakeupClock,
+                    rcc: &mut Rcc,
+                ) -> Result<(), InvalidConfig> {
+                    let freq = match clock {
+                        WakeupClock::HSI16 => HSI_FREQ,
+                        WakeupClock::LSE(_) => LSE_FREQ,
+                    };
+                    let brr = (u64::from(freq) * $brr_mul + u64::from(self.baudrate.0 / 2))
+                        / u64::from(self.baudrate.0);
+                    if brr < $brr_min || brr > $brr_max {
+                        return Err(InvalidConfig);
+                    }
+
+                    // Configuration bits below can only be written while the
+                    // peripheral is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+
+                    match clock {
+                        WakeupClock::HSI16 => {
+                            // Enable HSI16 and keep it available to the kernel
+                            // in Stop mode
+                            rcc.rb
+                                .cr
+                                .modify(|_, w| w.hsi16on().set_bit().hsi16keron().set_bit());
+                            while rcc.rb.cr.read().hsi16rdyf().bit_is_clear() {}
+                            rcc.rb.ccipr.modify(|_, w| w.$usartXsel().hsi16());
+                        }
+                        WakeupClock::LSE(_) => {
+                            rcc.rb.ccipr.modify(|_, w| w.$usartXsel().lse());
+                        }
+                    }
+
+                    self.usart.brr.write(|w| unsafe { w.bits(brr as u32) });
+
+                    Ok(())
+                }
+
+                /// Disables waking up the MCU from Stop mode
+                ///
+                /// The kernel clock selected by `enable_wakeup` stays active.
+                pub fn disable_wakeup(&mut self) {
+                    self.usart.cr1.modify(|_, w| w.uesm().clear_bit());
+                    self.usart
+                        .cr3
+                        .modify(|_, w| w.wufie().clear_bit().ucesm().clear_bit());
+                    self.clear_wakeup_flag();
+                }
+
+                /// Returns true if the wakeup flag is set
+                pub fn is_wakeup(&self) -> bool {
+                    self.usart.isr.read().wuf().bit_is_set()
+                }
+
+                /// Clears the wakeup flag
+                ///
+                /// This must be done after waking up, or the MCU will wake up
+                /// again immediately the next time it enters Stop mode.
+                pub fn clear_wakeup_flag(&mut self) {
+                    self.usart.icr.write(|w| w.wucf().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+usart_wakeup! {
+    LPUART1: (lpuart1sel, 256, 0x300, 0xfffff),
+    USART2: (usart2sel, 1, 16, 0xffff),
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+usart_wakeup! {
+    USART1: (usart1sel, 1, 16, 0xffff),
 }
 
 impl Serial<LPUART1> {
-    /// Switches LPUART1 clock course to LSE
+    /// Switches LPUART1 clock source to LSE
     ///
     /// Consumes LSE token, to get guarantee that
     /// LSE clocks are configured.
     ///
-    /// At the moment configured baudrate is ignored
-    /// and LPUART1 is forced to 9600 when clocked by LSE,
-    /// assuming that LSE is 32768
-    /// (and it must be so according to RM).
-    pub fn use_lse(&mut self, rcc: &mut Rcc, _: &LSE) {
-        //Disable transmitter
-        self.usart.cr1.modify(|_, w| w.te().disabled());
+    /// The baud rate is recalculated for LSE. Returns `InvalidConfig`, if the
+    /// configured baud rate can't be derived from LSE. Of the standard baud
+    /// rates, only 9600 Bd and below are possible.
+    pub fn use_lse(&mut self, rcc: &mut Rcc, lse: &LSE) -> Result<(), InvalidConfig> {
+        // Wait for the current transmission to finish
         while self.usart.isr.read().tc().bit_is_clear() {}
 
-        //Disable LPUART1
-        self.usart.cr1.modify(|_, w| w.ue().disabled());
-
-        //Reconfigure LPUART to use LSE
-        rcc.rb.ccipr.modify(|_, w| w.lpuart1sel().lse());
-
-        //Recalculate baudrate
-        //TODO requested baudrate value from the config should be stored somehow and used here
-        //but at the moment hardcoded 9600 will be used
-        //LSE is assumed to be 32768Hz, as RM says that LSE should only be 32768.
-        let brr = 256 * 32768 / 9600;
-        self.usart.brr.write(|w| unsafe { w.bits(brr) });
+        self.set_kernel_clock(WakeupClock::LSE(*lse), rcc)?;
 
         // Enable LPUART1
-        self.usart
-            .cr1
-            .write(|w| w.ue().set_bit().te().set_bit().re().set_bit());
+        self.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,202 @@
+//! Interrupt-driven serial port with software FIFOs
+
+use super::{Error, Event, Serial};
+use crate::hal;
+use crate::hal::prelude::*;
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+use super::USART1;
+#[cfg(feature = "io-STM32L071")]
+use super::{USART4, USART5};
+use super::{LPUART1, USART2};
+
+/// Ring buffer on top of caller-provided storage
+struct RingBuffer {
+    buffer: &'static mut [u8],
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    fn new(buffer: &'static mut [u8]) -> Self {
+        RingBuffer {
+            buffer,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns false, if the buffer is full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == self.buffer.len() {
+            return false;
+        }
+        let end = (self.start + self.len) % self.buffer.len();
+        self.buffer[end] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn peek(&self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.buffer[self.start])
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.start = (self.start + 1) % self.buffer.len();
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// Serial port with software RX and TX FIFOs
+///
+/// Received bytes are moved into the RX FIFO, and bytes to be sent are taken
+/// from the TX FIFO, by `on_interrupt`, which must be called from the USART's
+/// interrupt handler. No DMA channels are used.
+///
+/// As the interrupt handler and the application both need access, this is
+/// usually stored in a `cortex_m::interrupt::Mutex`, or an RTIC resource.
+pub struct Buffered<USART> {
+    serial: Serial<USART>,
+    rx_buffer: RingBuffer,
+    tx_buffer: RingBuffer,
+    error: Option<Error>,
+}
+
+macro_rules! buffered {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Buffered<$USARTX> {
+                /// Wraps `serial`, using the provided buffers as FIFOs
+                ///
+                /// This enables the RXNE interrupt. The USART's interrupt must be
+                /// unmasked in the NVIC by the user.
+                ///
+                /// # Panics
+                ///
+                /// Panics, if one of the buffers is empty.
+                pub fn new(
+                    mut serial: Serial<$USARTX>,
+                    rx_buffer: &'static mut [u8],
+                    tx_buffer: &'static mut [u8],
+                ) -> Self {
+                    assert!(!rx_buffer.is_empty() && !tx_buffer.is_empty());
+
+                    serial.listen(Event::Rxne);
+
+                    Buffered {
+                        serial,
+                        rx_buffer: RingBuffer::new(rx_buffer),
+                        tx_buffer: RingBuffer::new(tx_buffer),
+                        error: None,
+                    }
+                }
+
+                /// Moves bytes between the USART and the FIFOs
+                ///
+                /// Call this from the USART's interrupt handler. Reception errors,
+                /// and received bytes that don't fit into the RX FIFO, are
+                /// recorded and returned by the next call to `read`.
+                pub fn on_interrupt(&mut self) {
+                    loop {
+                        match self.serial.rx.read() {
+                            Ok(byte) => {
+                                if !self.rx_buffer.push(byte) {
+                                    self.record_error(Error::Overrun);
+                                }
+                            }
+                            Err(nb::Error::Other(err)) => self.record_error(err),
+                            Err(nb::Error::WouldBlock) => break,
+                        }
+                    }
+
+                    while let Some(byte) = self.tx_buffer.peek() {
+                        if self.serial.tx.write(byte).is_err() {
+                            break;
+                        }
+                        self.tx_buffer.pop();
+                    }
+                    if self.tx_buffer.is_empty() {
+                        self.serial.unlisten(Event::Txe);
+                    }
+                }
+
+                /// Disables the interrupts and returns the serial port and buffers
+                ///
+                /// Bytes still in the FIFOs are discarded.
+                pub fn release(mut self) -> (Serial<$USARTX>, &'static mut [u8], &'static mut [u8]) {
+                    self.serial.unlisten(Event::Rxne);
+                    self.serial.unlisten(Event::Txe);
+                    (self.serial, self.rx_buffer.buffer, self.tx_buffer.buffer)
+                }
+
+                fn record_error(&mut self, err: Error) {
+                    // Keep the first error, as later ones are often caused by it
+                    if self.error.is_none() {
+                        self.error = Some(err);
+                    }
+                }
+            }
+
+            impl hal::serial::Read<u8> for Buffered<$USARTX> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    if let Some(err) = self.error.take() {
+                        return Err(nb::Error::Other(err));
+                    }
+                    self.rx_buffer.pop().ok_or(nb::Error::WouldBlock)
+                }
+            }
+
+            impl hal::serial::Write<u8> for Buffered<$USARTX> {
+                type Error = Error;
+
+                fn flush(&mut self) -> nb::Result<(), Error> {
+                    if !self.tx_buffer.is_empty() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    self.serial.tx.flush()
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    if !self.tx_buffer.push(byte) {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    self.serial.listen(Event::Txe);
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+#[cfg(any(
+    feature = "io-STM32L021",
+    feature = "io-STM32L031",
+    feature = "io-STM32L051",
+    feature = "io-STM32L071",
+))]
+buffered! {
+    LPUART1,
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+buffered! {
+    USART1,
+}
+
+#[cfg(feature = "io-STM32L071")]
+buffered! {
+    USART4,
+    USART5,
+}
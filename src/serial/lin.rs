@@ -0,0 +1,111 @@
+//! LIN (Local Interconnect Network) frame helpers
+//!
+//! See `Serial::enable_lin` and the `lin_*` methods of `Serial` for the
+//! hardware side of things.
+
+/// LIN frame error
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Error of the underlying serial port
+    Serial(super::Error),
+    /// The sync byte following the break was not `0x55`
+    Sync,
+    /// The parity bits of the protected identifier are wrong
+    Identifier,
+    /// The checksum of the received response is wrong
+    Checksum,
+    /// A transmitted byte was not read back from the bus unchanged
+    Readback,
+    /// No byte was received within the receiver timeout
+    Timeout,
+}
+
+impl From<super::Error> for Error {
+    fn from(err: super::Error) -> Self {
+        Error::Serial(err)
+    }
+}
+
+/// LIN checksum model
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// Checksum over the data bytes only (LIN 1.x, and diagnostic frames)
+    Classic,
+    /// Checksum over the protected identifier and the data bytes (LIN 2.x)
+    Enhanced,
+}
+
+/// The sync byte, sent after the break of each header
+pub const SYNC: u8 = 0x55;
+
+/// Receiver timeout set up by `Serial::enable_lin`, in bit times
+///
+/// The LIN specification allows a header and a response to take 40% longer
+/// than their nominal length. For a frame with 8 data bytes, this adds up to
+/// 50 bit times, which bounds the gap between two bytes of a frame.
+pub(super) const TIMEOUT_BITS: u32 = 50;
+
+/// Returns the protected identifier for the 6-bit frame identifier `id`
+pub fn protected_id(id: u8) -> u8 {
+    let id = id & 0x3f;
+    let bit = |n: u8| (id >> n) & 1;
+
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+    id | p0 << 6 | p1 << 7
+}
+
+/// Returns the frame identifier of the protected identifier `pid`
+///
+/// Returns `Error::Identifier`, if the parity bits are wrong.
+pub fn frame_id(pid: u8) -> Result<u8, Error> {
+    let id = pid & 0x3f;
+    if protected_id(id) == pid {
+        Ok(id)
+    } else {
+        Err(Error::Identifier)
+    }
+}
+
+/// Computes the checksum of a frame
+///
+/// `pid` is only included for `Checksum::Enhanced`.
+pub fn checksum(pid: u8, data: &[u8], model: Checksum) -> u8 {
+    let init = match model {
+        Checksum::Classic => 0,
+        Checksum::Enhanced => u16::from(pid),
+    };
+
+    let sum = data.iter().fold(init, |sum, &byte| {
+        let sum = sum + u16::from(byte);
+        // Add the carry back in
+        (sum & 0xff) + (sum >> 8)
+    });
+
+    !(sum as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_identifiers() {
+        assert_eq!(protected_id(0x00), 0x80);
+        assert_eq!(protected_id(0x01), 0xc1);
+        assert_eq!(protected_id(0x3c), 0x3c);
+        assert_eq!(protected_id(0x3d), 0x7d);
+
+        assert_eq!(frame_id(0x7d).unwrap(), 0x3d);
+        assert!(frame_id(0x3d).is_err());
+    }
+
+    #[test]
+    fn checksums() {
+        // Example from the LIN 2.x specification
+        assert_eq!(checksum(0x4a, &[0x55, 0x93, 0xe5], Checksum::Enhanced), 0xe6);
+        assert_eq!(checksum(0x4a, &[0x55, 0x93, 0xe5], Checksum::Classic), 0x31);
+    }
+}
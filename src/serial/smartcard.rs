@@ -0,0 +1,358 @@
+//! ISO 7816-3 smartcard mode
+//!
+//! Supported by USART1 and USART2. The card's I/O line is connected to the TX
+//! pin, which must be configured as an open drain output, and the card's clock
+//! input to the CK pin. Driving the card's reset line is left to the user.
+
+use nb::block;
+
+use embedded_time::rate::{Extensions, Hertz};
+
+use super::{CkPin, Error as SerialError, HalfDuplexPin, InvalidConfig, Serial, StopBits};
+use crate::hal::prelude::*;
+use crate::rcc::Rcc;
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+use super::USART1;
+use super::USART2;
+
+/// Smartcard error
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Error of the underlying serial port
+    ///
+    /// `Framing` is returned, if the card still signaled a parity error after
+    /// the configured number of retransmissions.
+    Serial(SerialError),
+    /// The card did not send a character within the waiting time
+    Timeout,
+    /// The ATR is malformed, or uses the inverse convention
+    InvalidAtr,
+}
+
+impl From<SerialError> for Error {
+    fn from(err: SerialError) -> Self {
+        Error::Serial(err)
+    }
+}
+
+/// Smartcard configuration
+pub struct Config {
+    /// Frequency of the clock provided to the card
+    pub clock: Hertz,
+    /// Clock cycles per elementary time unit (F/D, 372 by default)
+    pub etu_clocks: u16,
+    /// Guard time, in elementary time units
+    pub guard_time: u8,
+    /// Number of retransmissions on a parity error (0 to 7)
+    pub retries: u8,
+    /// Send a NACK on parity errors
+    pub nack: bool,
+    /// Maximum time between two characters sent by the card, in elementary
+    /// time units
+    pub waiting_time: u32,
+}
+
+impl Config {
+    pub fn clock(mut self, clock: impl Into<Hertz>) -> Self {
+        self.clock = clock.into();
+        self
+    }
+
+    pub fn etu_clocks(mut self, etu_clocks: u16) -> Self {
+        self.etu_clocks = etu_clocks;
+        self
+    }
+
+    pub fn guard_time(mut self, guard_time: u8) -> Self {
+        self.guard_time = guard_time;
+        self
+    }
+
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn nack(mut self, nack: bool) -> Self {
+        self.nack = nack;
+        self
+    }
+
+    pub fn waiting_time(mut self, waiting_time: u32) -> Self {
+        self.waiting_time = waiting_time;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            clock: Hertz(4_000_000),
+            etu_clocks: 372,
+            guard_time: 16,
+            retries: 3,
+            nack: true,
+            waiting_time: 9600,
+        }
+    }
+}
+
+/// Answer To Reset
+pub struct Atr {
+    bytes: [u8; 33],
+    len: usize,
+    historical: usize,
+}
+
+impl Atr {
+    /// Returns the complete ATR, starting with TS
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// Returns the historical bytes
+    pub fn historical_bytes(&self) -> &[u8] {
+        let k = usize::from(self.bytes[1] & 0x0f);
+        &self.bytes[self.historical..self.historical + k]
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), Error> {
+        if self.len == self.bytes.len() {
+            return Err(Error::InvalidAtr);
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Smartcard interface
+pub struct Smartcard<USART> {
+    serial: Serial<USART>,
+}
+
+macro_rules! smartcard {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl Smartcard<$USARTX> {
+                /// Configures the USART for smartcard mode
+                ///
+                /// Returns `InvalidConfig`, if the card clock can't be derived from
+                /// the kernel clock selected in `RCC_CCIPR`, if `config.retries` is
+                /// larger than 7, or if `config.waiting_time` doesn't fit into 24
+                /// bits.
+                pub fn new<IO, CK>(
+                    usart: $USARTX,
+                    io: IO,
+                    ck: CK,
+                    config: Config,
+                    rcc: &mut Rcc,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    IO: HalfDuplexPin<$USARTX>,
+                    CK: CkPin<$USARTX>,
+                {
+                    // The card clock is the kernel clock divided by 2 * PSC
+                    let kernel_clk = Serial::<$USARTX>::kernel_clk(rcc);
+                    let psc = (kernel_clk + config.clock.0) / (2 * config.clock.0);
+                    if psc == 0 || psc > 0b11111 || config.retries > 0b111 {
+                        return Err(InvalidConfig);
+                    }
+                    if config.waiting_time > 0xff_ffff {
+                        return Err(InvalidConfig);
+                    }
+                    let brr = 2 * psc * u32::from(config.etu_clocks);
+                    if brr > 0xffff {
+                        return Err(InvalidConfig);
+                    }
+
+                    io.setup();
+                    ck.setup();
+
+                    // Smartcard characters consist of 8 data bits, an even parity
+                    // bit and 1.5 stop bits.
+                    let serial_config = super::Config::default()
+                        .baudrate((kernel_clk / brr).Bd())
+                        .wordlength_9()
+                        .parity_even()
+                        .stopbits(StopBits::STOP1P5);
+                    let serial = Serial::configure(usart, serial_config, rcc)?;
+
+                    // The bits below can only be written while the peripheral is
+                    // disabled. BRR is written again, as the baud rate above may
+                    // have been rounded.
+                    serial.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    serial.usart.brr.write(|w| unsafe { w.bits(brr) });
+                    serial.usart.gtpr.write(|w| unsafe {
+                        w.psc().bits(psc as u8).gt().bits(config.guard_time)
+                    });
+                    serial.usart.rtor.write(|w| unsafe { w.rto().bits(config.waiting_time) });
+                    serial.usart.cr2.modify(|_, w| unsafe {
+                        w.stop()
+                            .bits(0b11)
+                            .linen()
+                            .clear_bit()
+                            .clken()
+                            .set_bit()
+                            .rtoen()
+                            .set_bit()
+                    });
+                    serial.usart.cr3.modify(|_, w| unsafe {
+                        w.hdsel()
+                            .clear_bit()
+                            .iren()
+                            .clear_bit()
+                            .scarcnt()
+                            .bits(config.retries)
+                            .nack()
+                            .bit(config.nack)
+                            .scen()
+                            .set_bit()
+                    });
+                    serial.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    Ok(Smartcard { serial })
+                }
+
+                /// Receives the Answer To Reset
+                ///
+                /// Call this right after releasing the card's reset line. Only the
+                /// direct convention (TS = 0x3B) is supported.
+                pub fn read_atr(&mut self) -> Result<Atr, Error> {
+                    let mut atr = Atr {
+                        bytes: [0; 33],
+                        len: 0,
+                        historical: 0,
+                    };
+
+                    let ts = self.read_byte()?;
+                    if ts != 0x3b {
+                        return Err(Error::InvalidAtr);
+                    }
+                    atr.push(ts)?;
+
+                    let t0 = self.read_byte()?;
+                    atr.push(t0)?;
+
+                    // Interface bytes. Each TDi announces the presence of the next
+                    // group, and the protocol in its lower nibble.
+                    let mut indicator = t0 >> 4;
+                    let mut tck_present = false;
+                    loop {
+                        for i in 0..4 {
+                            if indicator & (1 << i) != 0 {
+                                let byte = self.read_byte()?;
+                                atr.push(byte)?;
+                            }
+                        }
+
+                        if indicator & 0b1000 == 0 {
+                            break;
+                        }
+                        let td = atr.bytes[atr.len - 1];
+                        if td & 0x0f != 0 {
+                            tck_present = true;
+                        }
+                        indicator = td >> 4;
+                    }
+
+                    atr.historical = atr.len;
+                    for _ in 0..(t0 & 0x0f) {
+                        let byte = self.read_byte()?;
+                        atr.push(byte)?;
+                    }
+
+                    if tck_present {
+                        let tck = self.read_byte()?;
+                        atr.push(tck)?;
+
+                        // XOR of all bytes from T0 up to and including TCK is 0
+                        if atr.as_bytes()[1..].iter().fold(0, |acc, b| acc ^ b) != 0 {
+                            return Err(Error::InvalidAtr);
+                        }
+                    }
+
+                    Ok(atr)
+                }
+
+                /// Sends `bytes` to the card
+                ///
+                /// Characters the card rejects are retransmitted by hardware.
+                pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+                    for &byte in bytes {
+                        block!(self.serial.tx.write(byte))?;
+                    }
+                    block!(self.serial.tx.flush())?;
+
+                    // The card still rejected a character after all retries
+                    let nacked = self.serial.usart.isr.read().fe().bit_is_set();
+
+                    // Sent characters are received too, as the receiver has to stay
+                    // enabled to detect NACKs. Discard them.
+                    self.serial.rx.clear_errors();
+                    self.serial.usart.rqr.write(|w| w.rxfrq().set_bit());
+
+                    if nacked {
+                        return Err(Error::Serial(SerialError::Framing));
+                    }
+                    Ok(())
+                }
+
+                /// Receives `buffer.len()` bytes from the card
+                pub fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+                    for byte in buffer {
+                        *byte = self.read_byte()?;
+                    }
+                    Ok(())
+                }
+
+                /// Sends a command, then receives `response.len()` bytes
+                ///
+                /// This is the basic T=0 exchange. Interpreting procedure bytes is
+                /// left to the caller.
+                pub fn transfer(&mut self, command: &[u8], response: &mut [u8]) -> Result<(), Error> {
+                    self.write(command)?;
+                    self.read(response)
+                }
+
+                /// Receives a single byte from the card
+                ///
+                /// Returns `Error::Timeout`, if the waiting time elapsed after the
+                /// previous character.
+                pub fn read_byte(&mut self) -> Result<u8, Error> {
+                    loop {
+                        match self.serial.rx.read() {
+                            Ok(byte) => return Ok(byte),
+                            Err(nb::Error::Other(err)) => return Err(err.into()),
+                            Err(nb::Error::WouldBlock) => {}
+                        }
+
+                        if self.serial.usart.isr.read().rtof().bit_is_set() {
+                            self.serial.usart.icr.write(|w| w.rtocf().set_bit());
+                            return Err(Error::Timeout);
+                        }
+                    }
+                }
+
+                /// Disables smartcard mode and returns the USART
+                pub fn release(self) -> $USARTX {
+                    self.serial.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.serial.usart.cr3.modify(|_, w| w.scen().clear_bit());
+                    self.serial.usart.cr2.modify(|_, w| w.clken().clear_bit().rtoen().clear_bit());
+                    self.serial.release()
+                }
+            }
+        )+
+    }
+}
+
+smartcard! {
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+smartcard! {
+    USART1,
+}
@@ -0,0 +1,128 @@
+//! USART synchronous master mode
+//!
+//! Supported by USART1, USART2, USART4 and USART5. The USART drives the clock on
+//! its CK pin while transmitting, which makes it usable as an additional SPI
+//! master. Chip select has to be driven by the user.
+
+use embedded_time::rate::{Extensions, Hertz};
+
+use super::{CkPin, Config, Error, InvalidConfig, Rx, RxPin, Serial, Tx, TxPin};
+use crate::hal;
+use crate::hal::prelude::*;
+use crate::rcc::Rcc;
+use crate::spi::{Mode, Phase, Polarity};
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+use super::USART1;
+use super::USART2;
+#[cfg(feature = "io-STM32L071")]
+use super::{USART4, USART5};
+
+/// USART in synchronous master mode
+pub struct SyncSerial<USART> {
+    serial: Serial<USART>,
+}
+
+macro_rules! sync_serial {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl SyncSerial<$USARTX> {
+                /// Configures the USART as a synchronous master
+                ///
+                /// Data is shifted out MSB first, with a clock pulse for every
+                /// bit, like on an SPI bus. Returns `InvalidConfig`, if `freq` is
+                /// higher than 1/16 of the kernel clock.
+                pub fn new<CK, TX, RX>(
+                    usart: $USARTX,
+                    (ck, tx, rx): (CK, TX, RX),
+                    mode: Mode,
+                    freq: impl Into<Hertz>,
+                    rcc: &mut Rcc,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    CK: CkPin<$USARTX>,
+                    TX: TxPin<$USARTX>,
+                    RX: RxPin<$USARTX>,
+                {
+                    let freq = freq.into();
+                    if freq.0 == 0 || freq.0 > Serial::<$USARTX>::kernel_clk(rcc) / 16 {
+                        return Err(InvalidConfig);
+                    }
+
+                    ck.setup();
+                    tx.setup();
+                    rx.setup();
+
+                    let config = Config::default().baudrate(freq.0.Bd()).msb_first(true);
+                    let serial = Serial::configure(usart, config, rcc)?;
+
+                    // The clock configuration can only be written while the
+                    // peripheral is disabled
+                    serial.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    serial.usart.cr2.modify(|_, w| {
+                        w.cpol()
+                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .cpha()
+                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            // Output a clock pulse for the last data bit too
+                            .lbcl()
+                            .set_bit()
+                            .clken()
+                            .set_bit()
+                    });
+                    serial.usart.cr1.modify(|_, w| w.ue().set_bit());
+
+                    Ok(SyncSerial { serial })
+                }
+
+                /// Splits the interface into its transmitting and receiving halves
+                ///
+                /// This allows using the `Tx` and `Rx` DMA methods. As the clock is
+                /// only generated while transmitting, a DMA reception always needs a
+                /// transmission of the same length. Start the reception first, so
+                /// no received byte is missed.
+                pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
+                    self.serial.split()
+                }
+
+                /// Disables synchronous mode and returns the USART
+                pub fn release(self) -> $USARTX {
+                    self.serial.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.serial.usart.cr2.modify(|_, w| w.clken().clear_bit());
+                    self.serial.release()
+                }
+            }
+
+            impl hal::spi::FullDuplex<u8> for SyncSerial<$USARTX> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    self.serial.rx.read()
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    self.serial.tx.write(byte)
+                }
+            }
+
+            impl hal::blocking::spi::transfer::Default<u8> for SyncSerial<$USARTX> {}
+
+            impl hal::blocking::spi::write::Default<u8> for SyncSerial<$USARTX> {}
+        )+
+    }
+}
+
+sync_serial! {
+    USART2,
+}
+
+#[cfg(any(feature = "io-STM32L051", feature = "io-STM32L071"))]
+sync_serial! {
+    USART1,
+}
+
+#[cfg(feature = "io-STM32L071")]
+sync_serial! {
+    USART4,
+    USART5,
+}
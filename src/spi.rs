@@ -16,7 +16,7 @@ use crate::gpio::gpiob::*;
 
 use crate::gpio::{AltMode, Analog, OpenDrain, Output, PushPull};
 use crate::hal;
-use crate::pac::SPI1;
+use crate::pac::{spi1, SPI1};
 #[cfg(any(
     feature = "io-STM32L051",
     feature = "io-STM32L071",
@@ -24,15 +24,19 @@ use crate::pac::SPI1;
     feature = "stm32l0x3"
 ))]
 use crate::pac::SPI2;
-use crate::rcc::{Enable, Rcc};
+use crate::rcc::{Clocks, Enable, Rcc};
 
 pub use hal::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
 
+#[cfg(feature = "embedded-hal-1")]
+pub mod device;
+
 /// SPI error
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Error {
     Busy,
+    /// Frame format error (TI mode)
     FrameError,
     /// Overrun occurred
     Overrun,
@@ -42,6 +46,119 @@ pub enum Error {
     Crc,
 }
 
+/// Data line configuration
+#[derive(Clone, Copy)]
+pub enum Direction {
+    /// Separate MISO and MOSI lines
+    FullDuplex,
+    /// A single bidirectional data line (3-wire SPI)
+    ///
+    /// The data line is MOSI in master mode, and MISO in slave mode. Use
+    /// `Spi::set_bidirectional_output` to switch between transmitting and
+    /// receiving.
+    HalfDuplex,
+    /// Receive only
+    ///
+    /// In master mode, the clock is generated continuously while the SPI is
+    /// enabled.
+    RxOnly,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::spi::Error for Error {
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        match self {
+            Error::Overrun => embedded_hal_1::spi::ErrorKind::Overrun,
+            Error::ModeFault => embedded_hal_1::spi::ErrorKind::ModeFault,
+            Error::FrameError => embedded_hal_1::spi::ErrorKind::FrameFormat,
+            Error::Busy | Error::Crc => embedded_hal_1::spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// Frame format
+#[derive(Clone, Copy)]
+pub enum FrameFormat {
+    /// Motorola SPI frames
+    Motorola,
+    /// TI synchronous serial frames
+    ///
+    /// The clock polarity and phase are fixed, and the NSS pin is used as frame
+    /// sync signal. In master mode, use `Spi::with_hardware_nss` to output it.
+    Ti,
+}
+
+/// SPI configuration
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub mode: Mode,
+    pub lsb_first: bool,
+    pub direction: Direction,
+    /// CRC polynomial, if hardware CRC calculation is enabled
+    pub crc_polynomial: Option<u16>,
+    pub frame_format: FrameFormat,
+}
+
+impl Config {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn lsb_first(mut self, lsb_first: bool) -> Self {
+        self.lsb_first = lsb_first;
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn crc_polynomial(mut self, crc_polynomial: u16) -> Self {
+        self.crc_polynomial = Some(crc_polynomial);
+        self
+    }
+
+    pub fn frame_format(mut self, frame_format: FrameFormat) -> Self {
+        self.frame_format = frame_format;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: MODE_0,
+            lsb_first: false,
+            direction: Direction::FullDuplex,
+            crc_polynomial: None,
+            frame_format: FrameFormat::Motorola,
+        }
+    }
+}
+
+impl From<Mode> for Config {
+    fn from(mode: Mode) -> Config {
+        Config::default().mode(mode)
+    }
+}
+
+/// Computes the BR bits for the given frequency
+fn baud_rate_divider(apb_freq: Hertz, spi_freq: Hertz) -> u8 {
+    match apb_freq.0 / spi_freq.0 {
+        0 => unreachable!(),
+        1..=2 => 0b000,
+        3..=5 => 0b001,
+        6..=11 => 0b010,
+        12..=23 => 0b011,
+        24..=47 => 0b100,
+        48..=95 => 0b101,
+        96..=191 => 0b110,
+        _ => 0b111,
+    }
+}
+
 pub trait Pins<SPI> {
     fn setup(&self);
 }
@@ -54,6 +171,9 @@ pub trait PinMiso<SPI> {
 pub trait PinMosi<SPI> {
     fn setup(&self);
 }
+pub trait PinNss<SPI> {
+    fn setup(&self);
+}
 
 impl<SPI, SCK, MISO, MOSI> Pins<SPI> for (SCK, MISO, MOSI)
 where
@@ -115,6 +235,39 @@ macro_rules! pins {
     }
 }
 
+macro_rules! nss_pins {
+    ($($SPIX:ty: [$([$NSS:ty, $ALTMODENSS:path]),*])+) => {
+        $(
+            $(
+                impl PinNss<$SPIX> for $NSS {
+                    fn setup(&self) {
+                        self.set_alt_mode($ALTMODENSS);
+                    }
+                }
+            )*
+        )+
+    }
+}
+
+nss_pins! {
+    SPI1: [
+        [PA4<Analog>, AltMode::AF0],
+        [PA15<Analog>, AltMode::AF0]
+    ]
+}
+
+#[cfg(any(
+    feature = "io-STM32L051",
+    feature = "io-STM32L071",
+    feature = "stm32l0x2",
+    feature = "stm32l0x3"
+))]
+nss_pins! {
+    SPI2: [
+        [PB12<Analog>, AltMode::AF0]
+    ]
+}
+
 #[cfg(any(feature = "stm32l0x2", feature = "stm32l0x3"))]
 pins! {
     SPI1:
@@ -205,32 +358,51 @@ pins! {
         ]
 }
 
+/// SPI peripheral
+///
+/// `WORD` is the type of a data frame, `u8` or `u16`. Use `frame_size_16bit`
+/// to switch to 16 bit frames.
 #[derive(Debug)]
-pub struct Spi<SPI, PINS> {
+pub struct Spi<SPI, PINS, WORD = u8> {
     spi: SPI,
     pins: PINS,
+    _word: PhantomData<WORD>,
 }
 
 pub trait SpiExt<SPI>: Sized {
-    fn spi<PINS, T>(self, pins: PINS, mode: Mode, freq: T, rcc: &mut Rcc) -> Spi<SPI, PINS>
+    fn spi<PINS, C, T>(self, pins: PINS, config: C, freq: T, rcc: &mut Rcc) -> Spi<SPI, PINS>
     where
         PINS: Pins<SPI>,
+        C: Into<Config>,
         T: Into<Hertz>;
+
+    fn spi_slave<PINS, NSS, C>(
+        self,
+        pins: PINS,
+        nss: NSS,
+        config: C,
+        rcc: &mut Rcc,
+    ) -> Spi<SPI, (PINS, NSS)>
+    where
+        PINS: Pins<SPI>,
+        NSS: PinNss<SPI>,
+        C: Into<Config>;
 }
 
 macro_rules! spi {
-    ($($SPIX:ident: ($spiX:ident, $pclkX:ident),)+) => {
+    ($($SPIX:ident: ($spiX:ident, $spiX_slave:ident, $pclkX:ident),)+) => {
         $(
             impl<PINS> Spi<$SPIX, PINS> {
-                pub fn $spiX<T>(
+                pub fn $spiX<C, T>(
                     spi: $SPIX,
                     pins: PINS,
-                    mode: Mode,
+                    config: C,
                     freq: T,
                     rcc: &mut Rcc
                 ) -> Self
                 where
                 PINS: Pins<$SPIX>,
+                C: Into<Config>,
                 T: Into<Hertz>
                 {
                     pins.setup();
@@ -247,59 +419,334 @@ macro_rules! spi {
                         w.txdmaen().set_bit()
                     });
 
-                    let spi_freq = freq.into().0;
-                    let apb_freq = rcc.clocks.$pclkX().0;
-                    let br = match apb_freq / spi_freq {
-                        0 => unreachable!(),
-                        1..=2 => 0b000,
-                        3..=5 => 0b001,
-                        6..=11 => 0b010,
-                        12..=23 => 0b011,
-                        24..=47 => 0b100,
-                        48..=95 => 0b101,
-                        96..=191 => 0b110,
-                        _ => 0b111,
-                    };
+                    let br = baud_rate_divider(rcc.clocks.$pclkX(), freq.into());
+
+                    let config = config.into();
 
                     // mstr: master configuration
-                    // lsbfirst: MSB first
                     // ssm: enable software slave management (NSS pin free for other uses)
                     // ssi: set nss high = master mode
-                    // dff: 8 bit frames
-                    // bidimode: 2-line unidirectional
-                    // spe: enable the SPI bus
                     #[allow(unused)]
                     spi.cr1.write(|w| unsafe {
-                        w.cpha()
-                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
-                            .cpol()
-                            .bit(mode.polarity == Polarity::IdleHigh)
+                        Self::write_config(w, &config)
                             .mstr()
                             .set_bit()
                             .br()
                             .bits(br)
-                            .lsbfirst()
-                            .clear_bit()
                             .ssm()
                             .set_bit()
                             .ssi()
                             .set_bit()
-                            .rxonly()
-                            .clear_bit()
-                            .dff()
+                    });
+                    spi.cr2.modify(|_, w| w.frf().bit(matches!(config.frame_format, FrameFormat::Ti)));
+                    Self::enable_crc(&spi, &config);
+
+                    // enable the SPI bus
+                    spi.cr1.modify(|_, w| w.spe().set_bit());
+
+                    Spi { spi, pins, _word: PhantomData }
+                }
+
+                /// Creates an SPI slave
+                ///
+                /// The slave is selected by the master through the hardware `nss`
+                /// pin. The clock is provided by the master, so no frequency is
+                /// configured.
+                pub fn $spiX_slave<NSS, C>(
+                    spi: $SPIX,
+                    pins: PINS,
+                    nss: NSS,
+                    config: C,
+                    rcc: &mut Rcc,
+                ) -> Spi<$SPIX, (PINS, NSS)>
+                where
+                    PINS: Pins<$SPIX>,
+                    NSS: PinNss<$SPIX>,
+                    C: Into<Config>,
+                {
+                    pins.setup();
+                    nss.setup();
+
+                    // Enable clock for SPI
+                    <$SPIX>::enable(rcc);
+
+                    spi.cr2.write(|w| {
+                        // NSS is an input
+                        w.ssoe().clear_bit();
+                        // enable DMA reception
+                        w.rxdmaen().set_bit();
+                        // enable DMA transmission
+                        w.txdmaen().set_bit()
+                    });
+
+                    let config = config.into();
+
+                    // mstr: slave configuration
+                    // ssm: hardware slave management (NSS pin selects the slave)
+                    spi.cr1.write(|w| {
+                        Self::write_config(w, &config)
+                            .mstr()
                             .clear_bit()
-                            .bidimode()
+                            .ssm()
                             .clear_bit()
-                            .spe()
-                            .set_bit()
                     });
+                    spi.cr2.modify(|_, w| w.frf().bit(matches!(config.frame_format, FrameFormat::Ti)));
+                    Self::enable_crc(&spi, &config);
+
+                    // enable the SPI bus
+                    spi.cr1.modify(|_, w| w.spe().set_bit());
+
+                    Spi { spi, pins: (pins, nss), _word: PhantomData }
+                }
+            }
+
+            impl<PINS, WORD: Copy> Spi<$SPIX, PINS, WORD> {
+
+                /// Writes the clock mode, bit order and data line configuration
+                fn write_config<'w>(
+                    w: &'w mut spi1::cr1::W,
+                    config: &Config,
+                ) -> &'w mut spi1::cr1::W {
+                    let (bidimode, bidioe, rxonly) = match config.direction {
+                        Direction::FullDuplex => (false, false, false),
+                        // Start out transmitting, so the master doesn't generate
+                        // a clock right away
+                        Direction::HalfDuplex => (true, true, false),
+                        Direction::RxOnly => (false, false, true),
+                    };
+
+                    w.cpha()
+                        .bit(config.mode.phase == Phase::CaptureOnSecondTransition)
+                        .cpol()
+                        .bit(config.mode.polarity == Polarity::IdleHigh)
+                        .lsbfirst()
+                        .bit(config.lsb_first)
+                        .bidimode()
+                        .bit(bidimode)
+                        .bidioe()
+                        .bit(bidioe)
+                        .rxonly()
+                        .bit(rxonly)
+                }
+
+                /// Enables CRC calculation, if configured
+                ///
+                /// Must be called while the SPI is disabled.
+                fn enable_crc(spi: &$SPIX, config: &Config) {
+                    if let Some(polynomial) = config.crc_polynomial {
+                        #[allow(unused)]
+                        spi.crcpr.write(|w| unsafe { w.crcpoly().bits(polynomial) });
+                        spi.cr1.modify(|_, w| w.crcen().set_bit());
+                    }
+                }
+
+                /// Resets the CRC calculation
+                ///
+                /// Call this before each CRC protected transaction.
+                pub fn reset_crc(&mut self) {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().set_bit().spe().set_bit());
+                }
+
+                /// Transfers `words`, followed by the CRC
+                ///
+                /// Requires a CRC polynomial to be configured. The CRC has the size
+                /// of a frame. The received data replaces the contents of `words`,
+                /// and the received CRC is checked. Returns `Error::Crc`, if it
+                /// doesn't match. Does nothing, if `words` is empty.
+                pub fn transfer_with_crc(&mut self, words: &mut [WORD]) -> Result<(), Error> {
+                    let len = words.len();
+                    if len == 0 {
+                        return Ok(());
+                    }
+
+                    for (i, word) in words.iter_mut().enumerate() {
+                        nb::block!(self.send_frame(*word))?;
+                        if i == len - 1 {
+                            // The CRC is sent after the frame that was just written
+                            self.spi.cr1.modify(|_, w| w.crcnext().set_bit());
+                        }
+                        *word = nb::block!(self.read_frame())?;
+                    }
+
+                    // Wait for the CRC to be received
+                    loop {
+                        let sr = self.spi.sr.read();
+                        if sr.rxne().bit_is_set() {
+                            break;
+                        }
+                        self.check_errors(&sr)?;
+                    }
+                    self.check_crc()
+                }
+
+                /// Checks the received CRC
+                ///
+                /// Call this after a CRC protected DMA transfer has finished. The
+                /// CRC is sent automatically after the last word of the transfer.
+                /// Discards the received CRC, and returns `Error::Crc`, if it
+                /// didn't match.
+                pub fn check_crc(&mut self) -> Result<(), Error> {
+                    let sr = self.spi.sr.read();
+                    if sr.rxne().bit_is_set() {
+                        // Safe, because reading the data register only discards the
+                        // received CRC.
+                        let _ = unsafe {
+                            ptr::read_volatile(&self.spi.dr as *const _ as *const u16)
+                        };
+                    }
+                    if sr.crcerr().bit_is_set() {
+                        self.spi.sr.modify(|_, w| w.crcerr().clear_bit());
+                        return Err(Error::Crc);
+                    }
+                    Ok(())
+                }
+
+                /// Changes the clock mode, bit order and frequency of a master
+                ///
+                /// Waits for the current frame to be transferred first. The frame
+                /// size, CRC and frame format (Motorola/TI) settings are not
+                /// changed.
+                pub fn reconfigure<C, T>(&mut self, config: C, freq: T, clocks: &Clocks)
+                where
+                    C: Into<Config>,
+                    T: Into<Hertz>,
+                {
+                    let config = config.into();
+                    let br = baud_rate_divider(clocks.$pclkX(), freq.into());
+
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    #[allow(unused)]
+                    self.spi.cr1.modify(|_, w| unsafe {
+                        Self::write_config(w, &config).br().bits(br)
+                    });
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Selects the direction of the data line in half-duplex mode
+                ///
+                /// In master mode, the clock is generated continuously while
+                /// receiving, until this is called with `true` again.
+                pub fn set_bidirectional_output(&mut self, output: bool) {
+                    self.spi.cr1.modify(|_, w| w.bidioe().bit(output));
+                }
+
+                /// Switches to 16 bit frames
+                ///
+                /// Waits for the current frame to be transferred first.
+                pub fn frame_size_16bit(self) -> Spi<$SPIX, PINS, u16> {
+                    self.set_frame_size(true);
+                    Spi { spi: self.spi, pins: self.pins, _word: PhantomData }
+                }
+
+                /// Switches to 8 bit frames
+                ///
+                /// Waits for the current frame to be transferred first.
+                pub fn frame_size_8bit(self) -> Spi<$SPIX, PINS, u8> {
+                    self.set_frame_size(false);
+                    Spi { spi: self.spi, pins: self.pins, _word: PhantomData }
+                }
 
-                    Spi { spi, pins }
+                fn set_frame_size(&self, bits16: bool) {
+                    // DFF can only be written while the SPI is disabled
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.dff().bit(bits16));
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Returns the error flagged in `sr`, if any
+                fn check_errors(&mut self, sr: &spi1::sr::R) -> Result<(), Error> {
+                    if sr.ovr().bit_is_set() {
+                        Err(Error::Overrun)
+                    } else if sr.modf().bit_is_set() {
+                        Err(Error::ModeFault)
+                    } else if sr.crcerr().bit_is_set() {
+                        self.spi.sr.modify(|_, w| w.crcerr().clear_bit());
+                        Err(Error::Crc)
+                    } else if sr.fre().bit_is_set() {
+                        // Cleared by reading SR
+                        Err(Error::FrameError)
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                fn read_frame(&mut self) -> nb::Result<WORD, Error> {
+                    let sr = self.spi.sr.read();
+                    self.check_errors(&sr)?;
+
+                    if sr.rxne().bit_is_set() {
+                        // NOTE(read_volatile) read only the frame size, so no data
+                        // is lost in 8 bit mode
+                        Ok(unsafe {
+                            ptr::read_volatile(&self.spi.dr as *const _ as *const WORD)
+                        })
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn send_frame(&mut self, word: WORD) -> nb::Result<(), Error> {
+                    let sr = self.spi.sr.read();
+                    self.check_errors(&sr)?;
+
+                    if sr.txe().bit_is_set() {
+                        // NOTE(write_volatile) see note above
+                        unsafe {
+                            ptr::write_volatile(
+                                UnsafeCell::raw_get(&self.spi.dr as *const _ as *const UnsafeCell<WORD>),
+                                word,
+                            )
+                        }
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Lets the master drive the `nss` pin by hardware
+                ///
+                /// NSS is driven low while the SPI is enabled. As the STM32L0 SPI
+                /// has no NSS pulse mode, use `disable` and `enable` to release NSS
+                /// between transactions.
+                pub fn with_hardware_nss<NSS>(self, nss: NSS) -> Spi<$SPIX, (PINS, NSS), WORD>
+                where
+                    NSS: PinNss<$SPIX>,
+                {
+                    nss.setup();
+
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr2.modify(|_, w| w.ssoe().set_bit());
+                    self.spi.cr1.modify(|_, w| w.ssm().clear_bit().spe().set_bit());
+
+                    Spi { spi: self.spi, pins: (self.pins, nss), _word: PhantomData }
+                }
+
+                /// Enables the SPI
+                ///
+                /// With hardware NSS output, this asserts NSS.
+                pub fn enable(&mut self) {
+                    self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
+                /// Disables the SPI, after the current frame has been transferred
+                ///
+                /// With hardware NSS output, this releases NSS.
+                pub fn disable(&mut self) {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
                 }
 
                 pub fn free(self) -> ($SPIX, PINS) {
                     (self.spi, self.pins)
                 }
+            }
+
+            impl<PINS> Spi<$SPIX, PINS> {
 
                 pub fn read_all<Channel, Buffer>(
                     self,
@@ -406,68 +853,243 @@ macro_rules! spi {
                 }
             }
 
+            impl<PINS> Spi<$SPIX, PINS> {
+                /// Sends `tx_buffer`, while receiving into `rx_buffer`
+                ///
+                /// Both buffers must have the same length. The RX channel is
+                /// started before the TX channel, so no received word is missed.
+                ///
+                /// # Panics
+                ///
+                /// Panics, if the buffers have different lengths.
+                pub fn transfer_dma<TxChannel, TxBuffer, RxChannel, RxBuffer>(
+                    self,
+                    dma:        &mut dma::Handle,
+                    tx_channel: TxChannel,
+                    tx_buffer:  Pin<TxBuffer>,
+                    rx_channel: RxChannel,
+                    rx_buffer:  Pin<RxBuffer>,
+                ) -> DuplexTransfer<Self, TxChannel, Pin<TxBuffer>, Rx<$SPIX>, RxChannel, RxBuffer, dma::Ready>
+                    where
+                        Tx<$SPIX>:        dma::Target<TxChannel>,
+                        Rx<$SPIX>:        dma::Target<RxChannel>,
+                        TxChannel:        dma::Channel,
+                        RxChannel:        dma::Channel,
+                        TxBuffer:         Deref + 'static,
+                        TxBuffer::Target: AsSlice<Element=u8>,
+                        RxBuffer:         DerefMut + 'static,
+                        RxBuffer::Target: AsMutSlice<Element=u8>,
+                {
+                    assert_eq!(tx_buffer.len(), rx_buffer.len());
+
+                    let address = tx_buffer.as_slice().as_ptr() as u32;
+                    // Safe, because the transfer takes ownership of the buffer,
+                    // and the trait bounds guarantee that it can be read from.
+                    unsafe {
+                        self.duplex_dma(dma, tx_channel, tx_buffer, address, rx_channel, rx_buffer)
+                    }
+                }
+
+                /// Sends the contents of `buffer`, replacing them with the received
+                /// words
+                pub fn transfer_in_place_dma<TxChannel, RxChannel, Buffer>(
+                    self,
+                    dma:        &mut dma::Handle,
+                    tx_channel: TxChannel,
+                    rx_channel: RxChannel,
+                    buffer:     Pin<Buffer>,
+                ) -> DuplexTransfer<Self, TxChannel, (), Rx<$SPIX>, RxChannel, Buffer, dma::Ready>
+                    where
+                        Tx<$SPIX>:      dma::Target<TxChannel>,
+                        Rx<$SPIX>:      dma::Target<RxChannel>,
+                        TxChannel:      dma::Channel,
+                        RxChannel:      dma::Channel,
+                        Buffer:         DerefMut + 'static,
+                        Buffer::Target: AsMutSlice<Element=u8>,
+                {
+                    let address = buffer.as_ptr() as u32;
+                    // Safe, because the buffer is owned by the RX transfer. Each
+                    // word is sent before the word replacing it is received.
+                    unsafe {
+                        self.duplex_dma(dma, tx_channel, (), address, rx_channel, buffer)
+                    }
+                }
+
+                /// # Safety
+                ///
+                /// `tx_address` must point to `rx_buffer.len()` bytes, that can be
+                /// read from until the transfer is finished.
+                unsafe fn duplex_dma<TxChannel, TxBuffer, RxChannel, RxBuffer>(
+                    self,
+                    dma:        &mut dma::Handle,
+                    tx_channel: TxChannel,
+                    tx_buffer:  TxBuffer,
+                    tx_address: u32,
+                    rx_channel: RxChannel,
+                    rx_buffer:  Pin<RxBuffer>,
+                ) -> DuplexTransfer<Self, TxChannel, TxBuffer, Rx<$SPIX>, RxChannel, RxBuffer, dma::Ready>
+                    where
+                        Tx<$SPIX>:        dma::Target<TxChannel>,
+                        Rx<$SPIX>:        dma::Target<RxChannel>,
+                        TxChannel:        dma::Channel,
+                        RxChannel:        dma::Channel,
+                        RxBuffer:         DerefMut + 'static,
+                        RxBuffer::Target: AsMutSlice<Element=u8>,
+                {
+                    let num_words = rx_buffer.len();
+                    let address = &(*$SPIX::ptr()).dr as *const _ as u32;
+
+                    dma::configure_channel::<_, _, u8>(
+                        dma,
+                        &Tx::<$SPIX>(PhantomData),
+                        &tx_channel,
+                        tx_address,
+                        num_words,
+                        address,
+                        dma::Priority::high(),
+                        dma::Direction::memory_to_peripheral(),
+                        false,
+                    );
+                    let rx = dma::Transfer::new(
+                        dma,
+                        Rx(PhantomData),
+                        rx_channel,
+                        rx_buffer,
+                        num_words,
+                        address,
+                        dma::Priority::high(),
+                        dma::Direction::peripheral_to_memory(),
+                        false,
+                    );
+
+                    DuplexTransfer {
+                        target: self,
+                        tx_channel,
+                        tx_buffer,
+                        rx,
+                    }
+                }
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<PINS> embedded_hal_1::spi::ErrorType for Spi<$SPIX, PINS> {
+                type Error = Error;
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<PINS> embedded_hal_1::spi::SpiBus<u8> for Spi<$SPIX, PINS> {
+                fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                    for word in words {
+                        nb::block!(self.send_frame(0u8))?;
+                        *word = nb::block!(self.read_frame())?;
+                    }
+                    Ok(())
+                }
+
+                fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+                    for &word in words {
+                        nb::block!(self.send_frame(word))?;
+                        nb::block!(self.read_frame())?;
+                    }
+                    Ok(())
+                }
+
+                fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+                    let len = read.len().max(write.len());
+                    for i in 0..len {
+                        nb::block!(self.send_frame(write.get(i).copied().unwrap_or(0)))?;
+                        let word = nb::block!(self.read_frame())?;
+                        if let Some(read) = read.get_mut(i) {
+                            *read = word;
+                        }
+                    }
+                    Ok(())
+                }
+
+                fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                    for word in words {
+                        nb::block!(self.send_frame(*word))?;
+                        *word = nb::block!(self.read_frame())?;
+                    }
+                    Ok(())
+                }
+
+                fn flush(&mut self) -> Result<(), Error> {
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+                    Ok(())
+                }
+            }
+
+            #[cfg(feature = "embedded-hal-1")]
+            impl<PINS> device::Bus for Spi<$SPIX, PINS> {
+                fn reconfigure(&mut self, config: &device::DeviceConfig, clocks: &Clocks) {
+                    Spi::reconfigure(self, config.config, config.freq, clocks)
+                }
+            }
+
             impl SpiExt<$SPIX> for $SPIX {
-                fn spi<PINS, T>(self, pins: PINS, mode: Mode, freq: T, rcc: &mut Rcc) -> Spi<$SPIX, PINS>
+                fn spi<PINS, C, T>(self, pins: PINS, config: C, freq: T, rcc: &mut Rcc) -> Spi<$SPIX, PINS>
                 where
                     PINS: Pins<$SPIX>,
+                    C: Into<Config>,
                     T: Into<Hertz>
                     {
-                        Spi::$spiX(self, pins, mode, freq, rcc)
+                        Spi::$spiX(self, pins, config, freq, rcc)
                     }
+
+                fn spi_slave<PINS, NSS, C>(
+                    self,
+                    pins: PINS,
+                    nss: NSS,
+                    config: C,
+                    rcc: &mut Rcc,
+                ) -> Spi<$SPIX, (PINS, NSS)>
+                where
+                    PINS: Pins<$SPIX>,
+                    NSS: PinNss<$SPIX>,
+                    C: Into<Config>,
+                {
+                    Spi::$spiX_slave(self, pins, nss, config, rcc)
+                }
             }
 
             impl<PINS> hal::spi::FullDuplex<u8> for Spi<$SPIX, PINS> {
                 type Error = Error;
 
                 fn read(&mut self) -> nb::Result<u8, Error> {
-                    let sr = self.spi.sr.read();
-
-                    Err(if sr.ovr().bit_is_set() {
-                        nb::Error::Other(Error::Overrun)
-                    } else if sr.modf().bit_is_set() {
-                        nb::Error::Other(Error::ModeFault)
-                    } else if sr.crcerr().bit_is_set() {
-                        nb::Error::Other(Error::Crc)
-                    } else if sr.rxne().bit_is_set() {
-                        // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
-                        // reading a half-word)
-                        return Ok(unsafe {
-                            ptr::read_volatile(&self.spi.dr as *const _ as *const u8)
-                        });
-                    } else {
-                        nb::Error::WouldBlock
-                    })
+                    self.read_frame()
                 }
 
                 fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
-                    let sr = self.spi.sr.read();
+                    self.send_frame(byte)
+                }
+            }
 
-                    Err(if sr.ovr().bit_is_set() {
-                        nb::Error::Other(Error::Overrun)
-                    } else if sr.modf().bit_is_set() {
-                        nb::Error::Other(Error::ModeFault)
-                    } else if sr.crcerr().bit_is_set() {
-                        nb::Error::Other(Error::Crc)
-                    } else if sr.txe().bit_is_set() {
-                        // NOTE(write_volatile) see note above
-                        unsafe { ptr::write_volatile(UnsafeCell::raw_get(&self.spi.dr as *const _ as _), byte) }
-                        return Ok(());
-                    } else {
-                        nb::Error::WouldBlock
-                    })
+            impl<PINS> hal::spi::FullDuplex<u16> for Spi<$SPIX, PINS, u16> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    self.read_frame()
                 }
 
+                fn send(&mut self, word: u16) -> nb::Result<(), Error> {
+                    self.send_frame(word)
+                }
             }
 
             impl<PINS> crate::hal::blocking::spi::transfer::Default<u8> for Spi<$SPIX, PINS> {}
 
             impl<PINS> crate::hal::blocking::spi::write::Default<u8> for Spi<$SPIX, PINS> {}
+
+            impl<PINS> crate::hal::blocking::spi::transfer::Default<u16> for Spi<$SPIX, PINS, u16> {}
+
+            impl<PINS> crate::hal::blocking::spi::write::Default<u16> for Spi<$SPIX, PINS, u16> {}
         )+
     }
 }
 
 spi! {
-    SPI1: (spi1, apb2_clk),
+    SPI1: (spi1, spi1_slave, apb2_clk),
 }
 
 #[cfg(any(
@@ -477,25 +1099,25 @@ spi! {
     feature = "stm32l0x3"
 ))]
 spi! {
-    SPI2: (spi2, apb1_clk),
+    SPI2: (spi2, spi2_slave, apb1_clk),
 }
 
 /// Token used for DMA transfers
 ///
 /// This is an implementation detail. The user doesn't have to deal with this
 /// directly.
-pub struct Tx<I>(PhantomData<I>);
+pub struct Tx<I>(pub(crate) PhantomData<I>);
 
 /// Token used for DMA transfers
 ///
 /// This is an implementation detail. The user doesn't have to deal with this
 /// directly.
-pub struct Rx<I>(PhantomData<I>);
+pub struct Rx<I>(pub(crate) PhantomData<I>);
 
 /// Wrapper around a [`dma::Transfer`].
 pub struct Transfer<Target, Token, Channel, Buffer, State> {
-    target: Target,
-    inner: dma::Transfer<Token, Channel, Buffer, State>,
+    pub(crate) target: Target,
+    pub(crate) inner: dma::Transfer<Token, Channel, Buffer, State>,
 }
 
 impl<Target, Token, Channel, Buffer> Transfer<Target, Token, Channel, Buffer, dma::Ready>
@@ -557,3 +1179,122 @@ where
         }
     }
 }
+
+/// Full-duplex DMA transfer, using a TX and an RX channel
+///
+/// For in-place transfers, `TxBuffer` is `()`, as the buffer is owned by the
+/// RX transfer.
+pub struct DuplexTransfer<Target, TxChannel, TxBuffer, RxToken, RxChannel, RxBuffer, State> {
+    target: Target,
+    tx_channel: TxChannel,
+    tx_buffer: TxBuffer,
+    rx: dma::Transfer<RxToken, RxChannel, RxBuffer, State>,
+}
+
+/// Resources returned by a finished [`DuplexTransfer`]
+pub struct DuplexResources<Target, TxChannel, TxBuffer, RxChannel, RxBuffer> {
+    pub target: Target,
+    pub tx_channel: TxChannel,
+    pub tx_buffer: TxBuffer,
+    pub rx_channel: RxChannel,
+    pub rx_buffer: Pin<RxBuffer>,
+}
+
+impl<Target, TxChannel, TxBuffer, RxToken, RxChannel, RxBuffer>
+    DuplexTransfer<Target, TxChannel, TxBuffer, RxToken, RxChannel, RxBuffer, dma::Ready>
+where
+    TxChannel: dma::Channel,
+    RxToken: dma::Target<RxChannel>,
+    RxChannel: dma::Channel,
+{
+    /// Enables the provided interrupts on the RX channel
+    ///
+    /// As the RX transfer finishes last, this is enough to get notified about
+    /// the end of the whole transfer.
+    pub fn enable_interrupts(&mut self, interrupts: dma::Interrupts) {
+        self.rx.enable_interrupts(interrupts);
+    }
+
+    /// Start the DMA transfer
+    ///
+    /// The RX channel is started first, so it is ready when the first word is
+    /// received.
+    pub fn start(
+        self,
+    ) -> DuplexTransfer<Target, TxChannel, TxBuffer, RxToken, RxChannel, RxBuffer, dma::Started>
+    {
+        let rx = self.rx.start();
+        self.tx_channel.start();
+
+        DuplexTransfer {
+            target: self.target,
+            tx_channel: self.tx_channel,
+            tx_buffer: self.tx_buffer,
+            rx,
+        }
+    }
+}
+
+impl<Target, TxChannel, TxBuffer, RxToken, RxChannel, RxBuffer>
+    DuplexTransfer<Target, TxChannel, TxBuffer, RxToken, RxChannel, RxBuffer, dma::Started>
+where
+    TxChannel: dma::Channel,
+    RxChannel: dma::Channel,
+{
+    /// Indicates whether the transfer is still ongoing
+    pub fn is_active(&self) -> bool {
+        self.rx.is_active()
+    }
+
+    /// Waits for the transfer to finish and returns the owned resources
+    ///
+    /// This function will busily wait until the transfer is finished. If
+    /// either channel reports an error, both channels are stopped.
+    #[allow(clippy::type_complexity)]
+    pub fn wait(
+        self,
+    ) -> Result<
+        DuplexResources<Target, TxChannel, TxBuffer, RxChannel, RxBuffer>,
+        (
+            DuplexResources<Target, TxChannel, TxBuffer, RxChannel, RxBuffer>,
+            dma::Error,
+        ),
+    > {
+        let mut result = Ok(());
+        while self.rx.is_active() {
+            if self.rx.error_occured() || self.tx_channel.error_occured() {
+                result = Err(dma::Error);
+                break;
+            }
+        }
+
+        let rx = match result {
+            Ok(()) => match self.rx.wait() {
+                Ok(res) => res,
+                Err((res, err)) => {
+                    result = Err(err);
+                    res
+                }
+            },
+            Err(_) => self.rx.stop().0,
+        };
+
+        // The last word has been sent before it was received, so this only
+        // stops the TX channel after an error
+        self.tx_channel.stop();
+        self.tx_channel.clear_flags();
+
+        let res = DuplexResources {
+            target: self.target,
+            tx_channel: self.tx_channel,
+            tx_buffer: self.tx_buffer,
+            rx_channel: rx.channel,
+            rx_buffer: rx.buffer,
+        };
+
+        match result {
+            Ok(()) => Ok(res),
+            Err(err) => Err((res, err)),
+        }
+    }
+}
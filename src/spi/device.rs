@@ -0,0 +1,302 @@
+//! Shared SPI bus with per-device chip select
+//!
+//! The devices in this module implement the embedded-hal 1.0 `SpiDevice`
+//! trait for several devices on one `Spi`. Before each transaction, the bus is
+//! reconfigured with the device's clock mode and frequency, and the device's CS
+//! pin is asserted.
+//!
+//! `RefCellDevice` can be used when all devices are accessed from the same
+//! execution context. `CriticalSectionDevice` keeps interrupts disabled during
+//! each transaction, so devices can be used from different interrupt handlers
+//! or RTIC tasks.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal_1::spi::{ErrorType, Operation, SpiBus, SpiDevice};
+use embedded_time::rate::Hertz;
+
+use super::{Config, Error};
+use crate::hal::digital::v2::OutputPin;
+use crate::rcc::Clocks;
+
+/// An SPI bus that can be reconfigured between transactions
+///
+/// This is implemented for `Spi`.
+pub trait Bus: SpiBus<u8, Error = Error> {
+    fn reconfigure(&mut self, config: &DeviceConfig, clocks: &Clocks);
+}
+
+/// Bus configuration for a single device
+#[derive(Clone, Copy)]
+pub struct DeviceConfig {
+    pub config: Config,
+    pub freq: Hertz,
+}
+
+impl DeviceConfig {
+    pub fn new(config: impl Into<Config>, freq: impl Into<Hertz>) -> Self {
+        DeviceConfig {
+            config: config.into(),
+            freq: freq.into(),
+        }
+    }
+}
+
+/// An SPI bus shared by multiple devices
+pub struct SharedBus<BUS> {
+    // The bus is only taken out during DMA transactions
+    bus: RefCell<Option<BUS>>,
+}
+
+impl<BUS> SharedBus<BUS> {
+    pub const fn new(bus: BUS) -> Self {
+        SharedBus {
+            bus: RefCell::new(Some(bus)),
+        }
+    }
+
+    /// Returns the bus
+    pub fn into_inner(self) -> BUS {
+        self.bus
+            .into_inner()
+            .expect("SPI bus lost in DMA transaction")
+    }
+
+    /// # Panics
+    ///
+    /// Panics, if the bus is already in use.
+    fn with<R>(&self, f: impl FnOnce(&mut BUS) -> R) -> R {
+        let mut bus = self.bus.borrow_mut();
+        f(bus.as_mut().expect("SPI bus lost in DMA transaction"))
+    }
+
+    /// # Panics
+    ///
+    /// Panics, if the bus is already in use.
+    fn with_owned<R>(&self, f: impl FnOnce(BUS) -> (BUS, R)) -> R {
+        let mut bus = self.bus.borrow_mut();
+        let (owned, result) = f(bus.take().expect("SPI bus lost in DMA transaction"));
+        *bus = Some(owned);
+        result
+    }
+}
+
+/// Selects the device, runs `f`, and deselects the device
+fn select<BUS, CS, R>(
+    bus: &mut BUS,
+    cs: &mut CS,
+    config: &DeviceConfig,
+    clocks: &Clocks,
+    f: impl FnOnce(&mut BUS) -> Result<R, Error>,
+) -> Result<R, Error>
+where
+    BUS: Bus,
+    CS: OutputPin<Error = Infallible>,
+{
+    bus.reconfigure(config, clocks);
+
+    cs.set_low().ok();
+    let result = f(bus);
+    let flushed = bus.flush();
+    cs.set_high().ok();
+
+    let result = result?;
+    flushed?;
+    Ok(result)
+}
+
+/// Like `select`, but passes the bus to `f` by value
+fn select_owned<BUS, CS, R>(
+    mut bus: BUS,
+    cs: &mut CS,
+    config: &DeviceConfig,
+    clocks: &Clocks,
+    f: impl FnOnce(BUS) -> (BUS, R),
+) -> (BUS, Result<R, Error>)
+where
+    BUS: Bus,
+    CS: OutputPin<Error = Infallible>,
+{
+    bus.reconfigure(config, clocks);
+
+    cs.set_low().ok();
+    let (mut bus, result) = f(bus);
+    let flushed = bus.flush();
+    cs.set_high().ok();
+
+    (bus, flushed.map(|()| result))
+}
+
+fn run<BUS>(
+    bus: &mut BUS,
+    operations: &mut [Operation<'_, u8>],
+    clocks: &Clocks,
+) -> Result<(), Error>
+where
+    BUS: Bus,
+{
+    for operation in operations {
+        match operation {
+            Operation::Read(words) => bus.read(words)?,
+            Operation::Write(words) => bus.write(words)?,
+            Operation::Transfer(read, write) => bus.transfer(read, write)?,
+            Operation::TransferInPlace(words) => bus.transfer_in_place(words)?,
+            Operation::DelayNs(ns) => {
+                bus.flush()?;
+                let cycles = u64::from(*ns) * u64::from(clocks.sys_clk().0) / 1_000_000_000;
+                cortex_m::asm::delay(cycles as u32 + 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A device on a `SharedBus`, accessed from a single execution context
+///
+/// Using two devices on the same bus at the same time, for example from an
+/// interrupt handler, panics.
+pub struct RefCellDevice<'a, BUS, CS> {
+    bus: &'a SharedBus<BUS>,
+    cs: CS,
+    config: DeviceConfig,
+    clocks: Clocks,
+}
+
+impl<'a, BUS, CS> RefCellDevice<'a, BUS, CS>
+where
+    BUS: Bus,
+    CS: OutputPin<Error = Infallible>,
+{
+    /// Creates a device, and deasserts its CS pin
+    pub fn new(bus: &'a SharedBus<BUS>, mut cs: CS, config: DeviceConfig, clocks: Clocks) -> Self {
+        cs.set_high().ok();
+        RefCellDevice {
+            bus,
+            cs,
+            config,
+            clocks,
+        }
+    }
+
+    /// Runs a DMA transaction with the device selected
+    ///
+    /// The bus is passed to `f` by value, so the `Spi` DMA methods can be
+    /// used. `f` must wait for the transfer to finish, and return the bus.
+    pub fn dma_transaction<R>(&mut self, f: impl FnOnce(BUS) -> (BUS, R)) -> Result<R, Error> {
+        let Self {
+            bus,
+            ref mut cs,
+            ref config,
+            ref clocks,
+        } = *self;
+
+        bus.with_owned(|bus| select_owned(bus, cs, config, clocks, f))
+    }
+
+    /// Returns the CS pin
+    pub fn free(self) -> CS {
+        self.cs
+    }
+}
+
+impl<BUS, CS> ErrorType for RefCellDevice<'_, BUS, CS> {
+    type Error = Error;
+}
+
+impl<BUS, CS> SpiDevice<u8> for RefCellDevice<'_, BUS, CS>
+where
+    BUS: Bus,
+    CS: OutputPin<Error = Infallible>,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Error> {
+        let Self {
+            bus,
+            ref mut cs,
+            ref config,
+            ref clocks,
+        } = *self;
+
+        bus.with(|bus| select(bus, cs, config, clocks, |bus| run(bus, operations, clocks)))
+    }
+}
+
+/// A device on a `SharedBus`, that can be accessed from any execution context
+///
+/// Interrupts are disabled during each transaction.
+pub struct CriticalSectionDevice<'a, BUS, CS> {
+    bus: &'a Mutex<SharedBus<BUS>>,
+    cs: CS,
+    config: DeviceConfig,
+    clocks: Clocks,
+}
+
+impl<'a, BUS, CS> CriticalSectionDevice<'a, BUS, CS>
+where
+    BUS: Bus,
+    CS: OutputPin<Error = Infallible>,
+{
+    /// Creates a device, and deasserts its CS pin
+    pub fn new(
+        bus: &'a Mutex<SharedBus<BUS>>,
+        mut cs: CS,
+        config: DeviceConfig,
+        clocks: Clocks,
+    ) -> Self {
+        cs.set_high().ok();
+        CriticalSectionDevice {
+            bus,
+            cs,
+            config,
+            clocks,
+        }
+    }
+
+    /// Runs a DMA transaction with the device selected
+    ///
+    /// See `RefCellDevice::dma_transaction`. Interrupts stay disabled until
+    /// `f` returns.
+    pub fn dma_transaction<R>(&mut self, f: impl FnOnce(BUS) -> (BUS, R)) -> Result<R, Error> {
+        let Self {
+            bus,
+            ref mut cs,
+            ref config,
+            ref clocks,
+        } = *self;
+
+        interrupt::free(|critical| {
+            bus.borrow(critical)
+                .with_owned(|bus| select_owned(bus, cs, config, clocks, f))
+        })
+    }
+
+    /// Returns the CS pin
+    pub fn free(self) -> CS {
+        self.cs
+    }
+}
+
+impl<BUS, CS> ErrorType for CriticalSectionDevice<'_, BUS, CS> {
+    type Error = Error;
+}
+
+impl<BUS, CS> SpiDevice<u8> for CriticalSectionDevice<'_, BUS, CS>
+where
+    BUS: Bus,
+    CS: OutputPin<Error = Infallible>,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Error> {
+        let Self {
+            bus,
+            ref mut cs,
+            ref config,
+            ref clocks,
+        } = *self;
+
+        interrupt::free(|critical| {
+            bus.borrow(critical)
+                .with(|bus| select(bus, cs, config, clocks, |bus| run(bus, operations, clocks)))
+        })
+    }
+}